@@ -0,0 +1,726 @@
+use crate::{
+    format::placeholders,
+    token::Token,
+    types::{Function, Type},
+};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Largest count of `pick` and `roll` the checker follows
+const MAX_PICK: usize = 255;
+
+/// How many values a piece of code pops and pushes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Effect {
+    pub inputs: usize,
+    pub outputs: usize,
+}
+
+impl fmt::Display for Effect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "( {} -- {} )", self.inputs, self.outputs)
+    }
+}
+
+/// What the checker knows about a value on stack
+#[derive(Debug, Clone, PartialEq)]
+enum Ty {
+    Any,
+    Num,
+    Str,
+    Arr,
+    Ptr,
+    Quote(Option<Effect>),
+}
+
+impl Ty {
+    fn to_type(&self) -> Type {
+        match self {
+            Self::Any => Type::Any,
+            Self::Num => Type::Num,
+            Self::Str => Type::Str,
+            Self::Arr => Type::Arr,
+            Self::Ptr => Type::Ptr,
+            Self::Quote(_) => Type::Quote,
+        }
+    }
+
+    fn from_type(ty: Type) -> Self {
+        match ty {
+            Type::Any => Self::Any,
+            Type::Num => Self::Num,
+            Type::Str => Self::Str,
+            Type::Arr => Self::Arr,
+            Type::Ptr => Self::Ptr,
+            Type::Quote => Self::Quote(None),
+        }
+    }
+
+    /// True when the type is known and isn't `expected`
+    fn conflicts(&self, expected: Type) -> bool {
+        *self != Self::Any && !expected.accepts(self.to_type())
+    }
+}
+
+/// A problem found by the checker
+pub struct Diagnostic {
+    /// Function the problem is in, `None` for the top level
+    pub function: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.function {
+            Some(name) => write!(f, "fn {}: {}", name, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Abstract stack of a block, values popped past its start are inputs
+#[derive(Default)]
+struct Block {
+    stack: Vec<Ty>,
+    inputs: usize,
+    /// The block always throws, nothing after the throw runs
+    diverges: bool,
+}
+
+impl Block {
+    fn pop(&mut self) -> Ty {
+        self.stack.pop().unwrap_or_else(|| {
+            self.inputs += 1;
+            Ty::Any
+        })
+    }
+
+    fn push(&mut self, ty: Ty) {
+        self.stack.push(ty);
+    }
+
+    fn apply(&mut self, effect: Effect) {
+        self.apply_typed(effect.inputs, &vec![Ty::Any; effect.outputs]);
+    }
+
+    fn apply_typed(&mut self, inputs: usize, outputs: &[Ty]) {
+        for _ in 0..inputs {
+            self.pop();
+        }
+        for ty in outputs {
+            self.push(ty.clone());
+        }
+    }
+
+    fn effect(&self) -> Effect {
+        Effect {
+            inputs: self.inputs,
+            outputs: self.stack.len(),
+        }
+    }
+}
+
+/// Static analysis of the stack effects of a program
+///
+/// Every token has a known number of inputs and outputs, the checker follows
+/// them through the program to find underflows and blocks whose effect on
+/// the stack depth depends on whether they run. Code whose effect can't be
+/// known statically (a quote that isn't a literal, a recursive function
+/// without a declared effect, ...) ends the analysis of the block it is in
+#[derive(Default)]
+pub struct Checker {
+    /// Effects of the functions defined so far, `None` when unknown
+    effects: HashMap<String, Option<Effect>>,
+    /// Functions defined so far, for their type annotations
+    definitions: HashMap<String, Function>,
+    /// Function being checked
+    function: Option<String>,
+    /// Annotated params of the function being checked
+    params: HashMap<String, Type>,
+    pub diagnostics: Vec<Diagnostic>,
+    /// Inferred effect of every function, in definition order
+    pub functions: Vec<(String, Option<Effect>)>,
+}
+
+impl Checker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Checks a whole program
+    pub fn check(&mut self, tokens: &[Token]) {
+        let mut block = Block::default();
+        self.block(tokens, &mut block, true);
+    }
+
+    fn report(&mut self, message: String) {
+        self.diagnostics.push(Diagnostic {
+            function: self.function.clone(),
+            message,
+        });
+    }
+
+    /// A nested block checked on its own, `None` when its effect is unknown
+    fn nested_block(&mut self, tokens: &[Token]) -> Option<Block> {
+        let mut block = Block::default();
+
+        if self.block(tokens, &mut block, false) {
+            Some(block)
+        } else {
+            None
+        }
+    }
+
+    /// Effect of a nested block on its own, `None` when unknown
+    fn nested(&mut self, tokens: &[Token]) -> Option<Effect> {
+        self.nested_block(tokens).map(|b| b.effect())
+    }
+
+    /// Pops the args of a call to `function` and checks their known types
+    /// against its annotations
+    fn call_args(&mut self, function: &Function, block: &mut Block) {
+        for (arg, ty) in function.args.iter().zip(function.arg_types.iter()) {
+            let actual = block.pop();
+
+            if actual.conflicts(*ty) {
+                self.report(format!(
+                    "call {}: parameter {} expects {} but gets {}",
+                    function.name,
+                    arg.name,
+                    ty,
+                    actual.to_type()
+                ));
+            }
+        }
+    }
+
+    /// Checks a body run by `then` or `times`, it must leave the stack
+    /// depth as it was because it may run any number of times
+    fn body(&mut self, keyword: &str, effect: Option<Effect>, block: &mut Block) -> bool {
+        match effect {
+            Some(effect) => {
+                if effect.inputs != effect.outputs {
+                    self.report(format!(
+                        "body of {} changes the stack depth by {}, it must be the same whether it runs or not",
+                        keyword,
+                        effect.outputs as isize - effect.inputs as isize
+                    ));
+                }
+                block.apply(effect);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Infers the effect of a function and compares it with the declared one
+    fn function(&mut self, function: &Function, scope: &[Token]) {
+        let declared = function.effect.as_ref().map(|e| Effect {
+            inputs: e.inputs.len(),
+            outputs: e.outputs.len(),
+        });
+
+        // Recursive calls can only be followed through a declared effect
+        self.effects.insert(function.name.clone(), declared);
+        self.definitions.insert(function.name.clone(), function.clone());
+
+        let outer = self.function.replace(function.name.clone());
+        let params = function.args.iter().map(|a| a.name.clone()).zip(function.arg_types.iter().copied());
+        let outer_params = std::mem::replace(&mut self.params, params.collect());
+        let body = self.nested_block(scope);
+        let inferred = body.as_ref().map(|b| b.effect()).map(|body| Effect {
+            inputs: function.args.len() + body.inputs,
+            outputs: body.outputs,
+        });
+
+        if let (Some(declared), Some(inferred)) = (declared, inferred) {
+            if declared != inferred {
+                self.report(format!("declared as {} but the body is {}", declared, inferred));
+            }
+        }
+
+        if let (Some(results), Some(body)) = (&function.results, body.as_ref().filter(|b| !b.diverges)) {
+            if results.len() != body.stack.len() {
+                self.report(format!(
+                    "declares {} results but the body leaves {} values",
+                    results.len(),
+                    body.stack.len()
+                ));
+            } else {
+                for (i, (ty, actual)) in results.iter().zip(body.stack.iter()).enumerate() {
+                    if actual.conflicts(*ty) {
+                        self.report(format!("result {} should be {} but is {}", i + 1, ty, actual.to_type()));
+                    }
+                }
+            }
+        }
+        self.function = outer;
+        self.params = outer_params;
+
+        let effect = declared.or(inferred);
+        self.effects.insert(function.name.clone(), effect);
+        self.functions.push((function.name.clone(), inferred));
+    }
+
+    /// Follows tokens through `block`, returns false once the effect of the
+    /// rest of the block can't be known
+    fn block(&mut self, tokens: &[Token], block: &mut Block, top_level: bool) -> bool {
+        let mut iter = tokens.iter();
+        let mut previous: Option<&Token> = None;
+
+        while let Some(token) = iter.next() {
+            let known = match token {
+                Token::Number(_)
+                | Token::True
+                | Token::False
+                | Token::Pi
+                | Token::E
+                | Token::Rand
+                | Token::Depth
+                | Token::NowMs
+                | Token::UnixTime
+                | Token::Memusage
+                | Token::Gc
+                | Token::Heapusage => {
+                    block.push(Ty::Num);
+                    true
+                }
+
+                Token::Str(_) | Token::ReadAll => {
+                    block.push(Ty::Str);
+                    true
+                }
+
+                // Value and whether there was one
+                Token::ReadLine => {
+                    block.push(Ty::Str);
+                    block.push(Ty::Num);
+                    true
+                }
+
+                Token::ReadNum => {
+                    block.push(Ty::Num);
+                    block.push(Ty::Num);
+                    true
+                }
+
+                Token::MapNew => {
+                    block.push(Ty::Ptr);
+                    true
+                }
+
+                Token::Ident(name) => {
+                    let ty = self.params.get(name).map_or(Ty::Any, |ty| Ty::from_type(*ty));
+                    block.push(ty);
+                    true
+                }
+
+                // Numbers give numbers, pointer arithmetic gives pointers
+                Token::Add | Token::Minus | Token::Div | Token::Mul | Token::Mod => {
+                    let a = block.pop();
+                    let b = block.pop();
+
+                    for ty in [&a, &b] {
+                        if ty.conflicts(Type::Num) && ty.conflicts(Type::Ptr) {
+                            self.report(format!("`{}` needs numbers but gets {}", token, ty.to_type()));
+                        }
+                    }
+                    block.push(if a == b { a } else { Ty::Any });
+                    true
+                }
+
+                Token::Sqrt
+                | Token::Abs
+                | Token::Floor
+                | Token::Ceil
+                | Token::Round
+                | Token::Sin
+                | Token::Cos
+                | Token::Tan
+                | Token::Log
+                | Token::Exp => {
+                    let a = block.pop();
+                    if a.conflicts(Type::Num) {
+                        self.report(format!("`{}` needs a number but gets {}", token, a.to_type()));
+                    }
+                    block.push(Ty::Num);
+                    true
+                }
+
+                Token::JsonParse => {
+                    block.apply_typed(1, &[Ty::Any]);
+                    true
+                }
+
+                Token::JsonStringify => {
+                    block.apply_typed(1, &[Ty::Str]);
+                    true
+                }
+
+                Token::Sleep | Token::IsoTime => {
+                    let a = block.pop();
+                    if a.conflicts(Type::Num) {
+                        self.report(format!("`{}` needs a number but gets {}", token, a.to_type()));
+                    }
+                    if *token == Token::IsoTime {
+                        block.push(Ty::Str);
+                    }
+                    true
+                }
+
+                Token::Pow | Token::Min | Token::Max | Token::Gcd | Token::RandInt => {
+                    for _ in 0..2 {
+                        let ty = block.pop();
+                        if ty.conflicts(Type::Num) {
+                            self.report(format!("`{}` needs numbers but gets {}", token, ty.to_type()));
+                        }
+                    }
+                    block.push(Ty::Num);
+                    true
+                }
+
+                Token::Eq | Token::Noteq | Token::Bigger | Token::Smaller | Token::MapHas => {
+                    block.apply_typed(2, &[Ty::Num]);
+                    true
+                }
+
+                Token::Nth | Token::MapGet => {
+                    block.apply(Effect { inputs: 2, outputs: 1 });
+                    true
+                }
+
+                Token::Push => {
+                    block.apply_typed(2, &[Ty::Arr]);
+                    true
+                }
+
+                Token::Args => {
+                    block.push(Ty::Arr);
+                    true
+                }
+
+                Token::GetEnv => {
+                    block.apply_typed(1, &[Ty::Str, Ty::Num]);
+                    true
+                }
+
+                // Nothing after exit runs
+                Token::Exit => {
+                    block.pop();
+                    block.diverges = true;
+                    true
+                }
+
+                Token::ReadFile => {
+                    block.apply_typed(1, &[Ty::Str]);
+                    true
+                }
+
+                Token::ListDir => {
+                    block.apply_typed(1, &[Ty::Arr]);
+                    true
+                }
+
+                Token::Len | Token::Has(_) | Token::MapLen | Token::Exists => {
+                    block.apply_typed(1, &[Ty::Num]);
+                    true
+                }
+
+                Token::Keys | Token::MapKeys | Token::MapValues | Token::Shuffle => {
+                    block.apply_typed(1, &[Ty::Arr]);
+                    true
+                }
+
+                Token::Get(_) => {
+                    block.apply(Effect { inputs: 1, outputs: 1 });
+                    true
+                }
+
+                // A let with the name of a param hides its type
+                Token::Let(name) => {
+                    self.params.remove(name);
+                    block.pop();
+                    true
+                }
+
+                Token::Put | Token::Print | Token::DeleteFile | Token::Drop | Token::Set(_) | Token::Delete(_) => {
+                    block.pop();
+                    true
+                }
+
+                Token::Assert | Token::AssertStack | Token::Seed => {
+                    block.pop();
+                    true
+                }
+
+                Token::SetProp(_) | Token::MapDel | Token::AssertEq | Token::WriteFile | Token::AppendFile | Token::SetEnv => {
+                    block.apply(Effect { inputs: 2, outputs: 0 });
+                    true
+                }
+
+                Token::MapSet => {
+                    block.apply(Effect { inputs: 3, outputs: 0 });
+                    true
+                }
+
+                Token::Dup => {
+                    let ty = block.pop();
+                    block.push(ty.clone());
+                    block.push(ty);
+                    true
+                }
+
+                Token::Swap => {
+                    let a = block.pop();
+                    let b = block.pop();
+                    block.push(a);
+                    block.push(b);
+                    true
+                }
+
+                Token::Rot => {
+                    let a = block.pop();
+                    let b = block.pop();
+                    let c = block.pop();
+                    block.push(a);
+                    block.push(b);
+                    block.push(c);
+                    true
+                }
+
+                Token::Over | Token::Tuck | Token::TwoDup => {
+                    let b = block.pop();
+                    let a = block.pop();
+                    match token {
+                        Token::Over => block.stack.extend([a.clone(), b, a]),
+                        Token::Tuck => block.stack.extend([b.clone(), a, b]),
+                        _ => block.stack.extend([a.clone(), b.clone(), a, b]),
+                    }
+                    true
+                }
+
+                Token::Nip => {
+                    let b = block.pop();
+                    block.pop();
+                    block.push(b);
+                    true
+                }
+
+                Token::UnRot => {
+                    let c = block.pop();
+                    let b = block.pop();
+                    let a = block.pop();
+                    block.stack.extend([c, a, b]);
+                    true
+                }
+
+                Token::TwoDrop => {
+                    block.apply(Effect { inputs: 2, outputs: 0 });
+                    true
+                }
+
+                Token::TwoSwap => {
+                    let d = block.pop();
+                    let c = block.pop();
+                    let b = block.pop();
+                    let a = block.pop();
+                    block.stack.extend([c, d, a, b]);
+                    true
+                }
+
+                // Known when the count is written right before. Counts past
+                // MAX_PICK aren't followed, each value below the known stack
+                // would be one more input
+                Token::Pick | Token::Roll => match previous {
+                    Some(Token::Number(n)) if n.fract() == 0.0 && *n >= 0.0 && *n <= MAX_PICK as f64 => {
+                        block.pop();
+                        let mut values: Vec<Ty> = (0..=*n as usize).map(|_| block.pop()).collect();
+                        values.reverse();
+                        let deepest = if *token == Token::Pick { values[0].clone() } else { values.remove(0) };
+                        block.stack.extend(values);
+                        block.push(deepest);
+                        true
+                    }
+                    _ => false,
+                },
+
+                // Removes values of the caller too, their number isn't known
+                Token::Clear => false,
+
+                // Pushes one value when the global memory isn't empty
+                Token::Mempop => false,
+
+                Token::Then | Token::Times => {
+                    let keyword = token.to_string();
+                    let effect = match iter.as_slice().first() {
+                        Some(Token::Scope(body)) => {
+                            iter.next();
+                            self.nested(body)
+                        }
+                        _ => match block.pop() {
+                            Ty::Quote(effect) => effect,
+                            _ => None,
+                        },
+                    };
+
+                    // Condition or count
+                    block.pop();
+                    self.body(&keyword, effect, block)
+                }
+
+                Token::Exec => match block.pop() {
+                    Ty::Quote(Some(effect)) => {
+                        block.apply(effect);
+                        true
+                    }
+                    _ => false,
+                },
+
+                Token::Scope(body) => {
+                    let effect = self.nested(body);
+                    block.push(Ty::Quote(effect));
+                    true
+                }
+
+                // The body can only pop what it pushed itself
+                Token::Array(body) => match self.nested(body) {
+                    Some(effect) => {
+                        if effect.inputs > 0 {
+                            self.report(format!("stack underflow in `{}`", token));
+                        }
+                        block.push(Ty::Arr);
+                        true
+                    }
+                    None => false,
+                },
+
+                Token::Object(props) => {
+                    let mut known = true;
+
+                    for (name, value) in props {
+                        match self.nested(value) {
+                            Some(effect) => {
+                                if effect.outputs != 1 {
+                                    self.report(format!(
+                                        "property {} must push exactly one value but pushes {}",
+                                        name, effect.outputs
+                                    ));
+                                }
+                                block.apply(Effect {
+                                    inputs: effect.inputs,
+                                    outputs: 0,
+                                });
+                            }
+                            None => known = false,
+                        }
+                    }
+
+                    block.push(Ty::Ptr);
+                    known
+                }
+
+                Token::Import(tokens) => self.block(tokens, block, top_level),
+
+                // Tests run on their own, whatever they leave on stack is fine
+                Token::Test(_, _, body) => {
+                    self.nested(body);
+                    true
+                }
+
+                Token::Function(function) => {
+                    if let Some(Token::Scope(scope)) = iter.next() {
+                        self.function(function, scope);
+                    }
+                    true
+                }
+
+                Token::Throw => {
+                    block.pop();
+                    block.diverges = true;
+                    true
+                }
+
+                // The handler starts from the depth before try with the
+                // error on top, both ways must end at the same depth unless
+                // one of them always throws
+                Token::Try(body, handler) => match (self.nested_block(body), self.nested_block(handler)) {
+                    (Some(body), Some(handler)) => {
+                        let effect = handler.effect();
+                        let handler_effect = Effect {
+                            inputs: effect.inputs.saturating_sub(1),
+                            outputs: effect.outputs + 1 - effect.inputs.min(1),
+                        };
+
+                        match (body.diverges, handler.diverges) {
+                            (false, false) => {
+                                let depth = |e: Effect| e.outputs as isize - e.inputs as isize;
+
+                                if depth(body.effect()) != depth(handler_effect) {
+                                    self.report(format!(
+                                        "try body is {} but catch is {}",
+                                        body.effect(),
+                                        handler_effect
+                                    ));
+                                }
+                                block.apply(body.effect());
+                            }
+                            (true, false) => block.apply(handler_effect),
+                            (false, true) => block.apply(body.effect()),
+                            (true, true) => block.diverges = true,
+                        }
+                        true
+                    }
+                    _ => false,
+                },
+
+                // The template written right before tells how many values are taken
+                Token::Format | Token::Printf => match previous {
+                    Some(Token::Str(template)) => match placeholders(template) {
+                        Ok(n) => {
+                            block.apply_typed(n + 1, if *token == Token::Format { &[Ty::Str] } else { &[] });
+                            true
+                        }
+                        Err(e) => {
+                            self.report(format!("{} at `{}`", e, token));
+                            false
+                        }
+                    },
+                    _ => false,
+                },
+
+                Token::Call(name) => match (self.effects.get(name).copied(), self.definitions.get(name).cloned()) {
+                    (Some(Some(effect)), Some(function)) => {
+                        self.call_args(&function, block);
+
+                        let outputs: Vec<Ty> = match &function.results {
+                            Some(results) if results.len() == effect.outputs => {
+                                results.iter().map(|ty| Ty::from_type(*ty)).collect()
+                            }
+                            _ => vec![Ty::Any; effect.outputs],
+                        };
+                        block.apply_typed(effect.inputs.saturating_sub(function.args.len()), &outputs);
+                        true
+                    }
+                    _ => false,
+                },
+            };
+
+            if top_level && block.inputs > 0 {
+                self.report(format!("stack underflow at `{}`", token));
+                block.inputs = 0;
+            }
+
+            if !known {
+                return false;
+            }
+
+            if block.diverges {
+                return true;
+            }
+
+            previous = Some(token);
+        }
+
+        true
+    }
+}
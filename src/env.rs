@@ -0,0 +1,162 @@
+use crate::{
+    error::Error,
+    interpreter::StackType,
+    token::Token,
+    types::{Function, Let},
+};
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::fmt;
+use std::rc::Rc;
+
+/// Lets and functions defined in one scope, with the scope it is nested in
+#[derive(Debug, Default)]
+pub struct Env {
+    pub lets: Vec<Let>,
+    pub functions: Vec<Rc<Function>>,
+    pub parent: Option<EnvRef>,
+}
+
+/// Shared handle to an `Env`
+///
+/// Closures and functions keep the env they were defined in alive, two
+/// handles are equal only when they point to the same env
+#[derive(Clone, Default)]
+pub struct EnvRef(Rc<RefCell<Env>>);
+
+impl EnvRef {
+    /// New env nested in this one
+    pub fn child(&self) -> Self {
+        Self(Rc::new(RefCell::new(Env {
+            lets: vec![],
+            functions: vec![],
+            parent: Some(self.clone()),
+        })))
+    }
+
+    pub fn borrow(&self) -> std::cell::Ref<'_, Env> {
+        self.0.borrow()
+    }
+
+    pub fn borrow_mut(&self) -> std::cell::RefMut<'_, Env> {
+        self.0.borrow_mut()
+    }
+
+    /// Identity of the env, used to visit each env once
+    pub fn id(&self) -> usize {
+        Rc::as_ptr(&self.0) as usize
+    }
+
+    /// Defines a let in this env, it shadows lets with the same name in the
+    /// parents but can't be defined twice in the same env
+    pub fn define(&self, new_let: Let) -> Result<(), Error> {
+        let mut env = self.0.borrow_mut();
+
+        if env.lets.iter().any(|l| l.name == new_let.name) {
+            return Err(Error::AlreadyDefined(new_let.name));
+        }
+        env.lets.push(new_let);
+
+        Ok(())
+    }
+
+    /// Defines a function in this env, it replaces a function with the same
+    /// name defined in this env and shadows the ones of the parents
+    pub fn define_function(&self, function: Function) {
+        let mut env = self.0.borrow_mut();
+
+        match env.functions.iter_mut().find(|f| f.name == function.name) {
+            Some(f) => *f = Rc::new(function),
+            None => env.functions.push(Rc::new(function)),
+        }
+    }
+
+    /// Nearest function with this name and the env it was defined in
+    pub fn function(&self, name: &str) -> Option<(Rc<Function>, EnvRef)> {
+        let env = self.0.borrow();
+
+        match env.functions.iter().find(|f| f.name == name) {
+            Some(f) => Some((f.clone(), self.clone())),
+            None => env.parent.as_ref().and_then(|p| p.function(name)),
+        }
+    }
+
+    /// Names of every function visible from this env
+    pub fn function_names(&self) -> Vec<String> {
+        let env = self.0.borrow();
+        let mut names: Vec<String> = env.functions.iter().map(|f| f.name.clone()).collect();
+
+        if let Some(parent) = &env.parent {
+            names.extend(parent.function_names());
+        }
+
+        names
+    }
+
+    /// Names of every let visible from this env
+    pub fn names(&self) -> Vec<String> {
+        let env = self.0.borrow();
+        let mut names: Vec<String> = env.lets.iter().map(|l| l.name.clone()).collect();
+
+        if let Some(parent) = &env.parent {
+            names.extend(parent.names());
+        }
+
+        names
+    }
+
+    /// Value of the let, looking from this env out through its parents
+    pub fn get(&self, name: &str) -> Option<StackType> {
+        let env = self.0.borrow();
+
+        match env.lets.iter().find(|l| l.name == name) {
+            Some(l) => Some(l.value.clone()),
+            None => env.parent.as_ref().and_then(|p| p.get(name)),
+        }
+    }
+
+    /// Sets the nearest let with this name, returns false if there is none
+    pub fn set(&self, name: &str, value: StackType) -> bool {
+        let mut env = self.0.borrow_mut();
+
+        match env.lets.iter_mut().find(|l| l.name == name) {
+            Some(l) => {
+                l.value = value;
+                true
+            }
+            None => match &env.parent {
+                Some(p) => p.set(name, value),
+                None => false,
+            },
+        }
+    }
+}
+
+impl fmt::Debug for EnvRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "EnvRef({:x})", self.id())
+    }
+}
+
+impl PartialEq for EnvRef {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl PartialOrd for EnvRef {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        if self == other {
+            Some(Ordering::Equal)
+        } else {
+            None
+        }
+    }
+}
+
+/// A quote together with the env it was created in
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct Closure {
+    pub body: Rc<[Token]>,
+    pub env: EnvRef,
+}
@@ -0,0 +1,166 @@
+use crate::{interpreter::StackType, types::Type};
+use std::fmt;
+
+/// Errors the interpreter can report while running a program
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// A keyword needed a pointer on top of stack but got another value
+    ExpectedPointer(String),
+    /// The pointer doesn't point to a live heap entry
+    DanglingPointer(usize),
+    /// The pointer points to another kind of heap data than the keyword needs
+    WrongHeapData(String, &'static str),
+    /// Only strings and numbers can be map keys
+    InvalidKey(String),
+    /// The map doesn't have the key
+    MissingKey(String),
+    /// A keyword got a value of the wrong type
+    ExpectedType(String, &'static str),
+    /// Index is past the end of array
+    IndexOutOfRange(f64, usize),
+    /// A let with the same name is already defined in the scope
+    AlreadyDefined(String),
+    /// No let with this name is in scope, with the closest name if any
+    UndefinedLet(String, Option<String>),
+    /// No function or let with this name to call, with the closest name if any
+    UndefinedFunction(String, Option<String>),
+    /// Function, parameter, its type and the value it got
+    ArgType(String, String, Type, StackType),
+    /// Function, index of the result, its type and the value left on stack
+    ResultType(String, usize, Type, Option<StackType>),
+    /// A keyword needed more values than the stack has
+    StackUnderflow(String),
+    /// Arithmetic keyword and the two values it can't work on
+    InvalidOperands(&'static str, StackType, StackType),
+    /// The object doesn't have the property
+    MissingProperty(String),
+    /// A property of an object literal didn't push exactly one value
+    PropertyValue(String),
+    /// A value thrown by the program that no try caught
+    Thrown(StackType),
+    /// `assert`, `assert_eq` or `assert_stack` failed, with what it got
+    AssertionFailed(String),
+    /// Reading or writing outside the program failed
+    Io(String),
+    /// The text read isn't a number
+    NotANumber(String),
+    /// The keyword needs file access the interpreter doesn't have
+    PermissionDenied(String),
+    /// Math keyword and the operands it isn't defined for
+    Domain(&'static str, String),
+    /// Text that isn't valid JSON, or a value that can't be written as JSON
+    Json(String),
+    /// The template of `format` or `printf` is malformed or doesn't fit its values
+    InvalidFormat(String),
+    /// `setenv` got a name or value the environment can't hold
+    InvalidEnv(String),
+    /// `exit` stops the program with this exit code, try can't catch it
+    Exit(i32),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ExpectedPointer(keyword) => {
+                write!(f, "{} keyword needs a pointer data on stack", keyword)
+            }
+            Self::DanglingPointer(p) => write!(f, "0x{:x} is not pointing to anything on heap", p),
+            Self::WrongHeapData(keyword, expected) => {
+                write!(f, "{} keyword needs a pointer to {}", keyword, expected)
+            }
+            Self::InvalidKey(keyword) => {
+                write!(f, "{} keyword needs a string or number as map key", keyword)
+            }
+            Self::MissingKey(key) => write!(f, "Key {} is not in map", key),
+            Self::ExpectedType(keyword, expected) => {
+                write!(f, "{} keyword needs {} on stack", keyword, expected)
+            }
+            Self::IndexOutOfRange(index, len) => {
+                write!(f, "Index {} is out of range for array of length {}", index, len)
+            }
+            Self::AlreadyDefined(name) => {
+                write!(f, "Let {} is already defined in this scope, use set to change it", name)
+            }
+            Self::UndefinedLet(name, suggestion) => {
+                write!(f, "{} is not defined", name)?;
+                did_you_mean(f, suggestion)
+            }
+            Self::UndefinedFunction(name, suggestion) => {
+                write!(f, "Function {} is not defined", name)?;
+                did_you_mean(f, suggestion)
+            }
+            Self::ArgType(function, arg, ty, value) => write!(
+                f,
+                "fn {}: parameter {} expects {} but got {:?}",
+                function, arg, ty, value
+            ),
+            Self::ResultType(function, i, ty, value) => match value {
+                Some(value) => write!(f, "fn {}: result {} should be {} but is {:?}", function, i + 1, ty, value),
+                None => write!(f, "fn {}: result {} should be {} but the stack is empty", function, i + 1, ty),
+            },
+            Self::StackUnderflow(keyword) => write!(f, "Stack underflow at `{}`", keyword),
+            Self::InvalidOperands(keyword, a, b) => write!(f, "Can't {} {:?} and {:?}", keyword, a, b),
+            Self::MissingProperty(name) => write!(f, "Property {} is not defined", name),
+            Self::PropertyValue(name) => write!(f, "Property {} must push exactly one value", name),
+            Self::Thrown(value) => write!(f, "Uncaught {:?}", value),
+            Self::AssertionFailed(reason) => write!(f, "Assertion failed: {}", reason),
+            Self::Io(reason) => write!(f, "I/O error: {}", reason),
+            Self::NotANumber(text) => write!(f, "{:?} is not a number", text),
+            Self::Domain(keyword, operands) => write!(f, "{} is not defined for {}", keyword, operands),
+            Self::Json(reason) => write!(f, "JSON error: {}", reason),
+            Self::InvalidFormat(reason) => write!(f, "Invalid format {}", reason),
+            Self::InvalidEnv(reason) => write!(f, "Can't set environment variable: {}", reason),
+            Self::Exit(code) => write!(f, "Program exited with code {}", code),
+            Self::PermissionDenied(keyword) => {
+                write!(f, "{} keyword is not allowed, the interpreter is sandboxed", keyword)
+            }
+        }
+    }
+}
+
+impl Error {
+    /// Name of the error, the `kind` property of caught errors
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::ExpectedPointer(_) => "expected_pointer",
+            Self::DanglingPointer(_) => "dangling_pointer",
+            Self::WrongHeapData(..) => "wrong_heap_data",
+            Self::InvalidKey(_) => "invalid_key",
+            Self::MissingKey(_) => "missing_key",
+            Self::ExpectedType(..) => "expected_type",
+            Self::IndexOutOfRange(..) => "index_out_of_range",
+            Self::AlreadyDefined(_) => "already_defined",
+            Self::UndefinedLet(..) => "undefined_let",
+            Self::UndefinedFunction(..) => "undefined_function",
+            Self::ArgType(..) => "arg_type",
+            Self::ResultType(..) => "result_type",
+            Self::StackUnderflow(_) => "stack_underflow",
+            Self::InvalidOperands(..) => "invalid_operands",
+            Self::MissingProperty(_) => "missing_property",
+            Self::PropertyValue(_) => "property_value",
+            Self::Thrown(_) => "thrown",
+            Self::AssertionFailed(_) => "assertion_failed",
+            Self::Io(_) => "io",
+            Self::NotANumber(_) => "not_a_number",
+            Self::PermissionDenied(_) => "permission_denied",
+            Self::Domain(..) => "domain",
+            Self::Json(_) => "json",
+            Self::InvalidFormat(_) => "invalid_format",
+            Self::InvalidEnv(_) => "invalid_env",
+            Self::Exit(_) => "exit",
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e.to_string())
+    }
+}
+
+fn did_you_mean(f: &mut fmt::Formatter<'_>, suggestion: &Option<String>) -> fmt::Result {
+    match suggestion {
+        Some(s) => write!(f, ", did you mean `{}`?", s),
+        None => Ok(()),
+    }
+}
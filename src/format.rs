@@ -0,0 +1,234 @@
+use crate::{error::Error, interpreter::StackType};
+
+/// Where the value goes inside its width
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+/// Options of a placeholder, written after `:` as
+/// `[[fill]align][0][width][.precision][radix]`
+#[derive(Debug, Default)]
+struct Spec {
+    fill: Option<char>,
+    align: Option<Align>,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+    radix: Option<char>,
+}
+
+/// Piece of a template, text or a placeholder
+enum Part {
+    Text(String),
+    Value(Spec),
+}
+
+/// Largest width or precision, so a template can't ask for more memory
+/// than a line of output needs
+const MAX_WIDTH: usize = 65535;
+
+fn invalid(template: &str, reason: &str) -> Error {
+    Error::InvalidFormat(format!("{:?}: {}", template, reason))
+}
+
+fn align(c: char) -> Option<Align> {
+    match c {
+        '<' => Some(Align::Left),
+        '^' => Some(Align::Center),
+        '>' => Some(Align::Right),
+        _ => None,
+    }
+}
+
+fn spec(template: &str, text: &str) -> Result<Spec, Error> {
+    let mut spec = Spec::default();
+    let chars: Vec<char> = text.chars().collect();
+    let mut i = 0;
+
+    if let Some(a) = chars.get(1).and_then(|c| align(*c)) {
+        spec.fill = Some(chars[0]);
+        spec.align = Some(a);
+        i = 2;
+    } else if let Some(a) = chars.first().and_then(|c| align(*c)) {
+        spec.align = Some(a);
+        i = 1;
+    }
+
+    if chars.get(i) == Some(&'0') {
+        spec.zero = true;
+        i += 1;
+    }
+
+    // `None` when there are no digits
+    let number = |i: &mut usize, what: &str| {
+        let start = *i;
+        while chars.get(*i).is_some_and(|c| c.is_ascii_digit()) {
+            *i += 1;
+        }
+        if start == *i {
+            return Ok(None);
+        }
+        match chars[start..*i].iter().collect::<String>().parse::<usize>() {
+            Ok(n) if n <= MAX_WIDTH => Ok(Some(n)),
+            _ => Err(invalid(template, &format!("{} can be at most {}", what, MAX_WIDTH))),
+        }
+    };
+
+    spec.width = number(&mut i, "width")?.unwrap_or(0);
+
+    if chars.get(i) == Some(&'.') {
+        i += 1;
+        spec.precision =
+            Some(number(&mut i, "precision")?.ok_or_else(|| invalid(template, "precision needs a number after `.`"))?);
+    }
+
+    if let Some(&c) = chars.get(i).filter(|c| matches!(c, 'x' | 'X' | 'b' | 'o')) {
+        spec.radix = Some(c);
+        i += 1;
+    }
+
+    if i < chars.len() {
+        return Err(invalid(template, &format!("unknown option `{}`", text)));
+    }
+
+    Ok(spec)
+}
+
+fn parse(template: &str) -> Result<Vec<Part>, Error> {
+    let mut parts = vec![];
+    let mut text = String::new();
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                text.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                text.push('}');
+            }
+            '{' => {
+                let mut inner = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => inner.push(c),
+                        None => return Err(invalid(template, "`{` is not closed")),
+                    }
+                }
+
+                let spec = match inner.strip_prefix(':') {
+                    Some(options) => spec(template, options)?,
+                    None if inner.is_empty() => Spec::default(),
+                    None => return Err(invalid(template, "options of a placeholder start with `:`")),
+                };
+
+                parts.push(Part::Text(std::mem::take(&mut text)));
+                parts.push(Part::Value(spec));
+            }
+            '}' => return Err(invalid(template, "`}` without `{`, write `}}` for a brace")),
+            c => text.push(c),
+        }
+    }
+
+    parts.push(Part::Text(text));
+
+    Ok(parts)
+}
+
+/// Number of values the template takes
+pub fn placeholders(template: &str) -> Result<usize, Error> {
+    Ok(parse(template)?.iter().filter(|p| matches!(p, Part::Value(_))).count())
+}
+
+/// Writes one value with the options of its placeholder
+fn value(template: &str, spec: &Spec, value: &StackType) -> Result<String, Error> {
+    let numeric = matches!(value, StackType::Float(_) | StackType::Pointer(_));
+
+    let text = match (spec.radix, value) {
+        // Past u64::MAX the digits would be those of u64::MAX
+        (Some(radix), StackType::Float(n)) if n.fract() == 0.0 && n.abs() < u64::MAX as f64 => {
+            let digits = match radix {
+                'x' => format!("{:x}", n.abs() as u64),
+                'X' => format!("{:X}", n.abs() as u64),
+                'b' => format!("{:b}", n.abs() as u64),
+                _ => format!("{:o}", n.abs() as u64),
+            };
+            if *n < 0.0 {
+                format!("-{}", digits)
+            } else {
+                digits
+            }
+        }
+        (Some(radix), StackType::Pointer(p)) => match radix {
+            'x' => format!("{:x}", p),
+            'X' => format!("{:X}", p),
+            'b' => format!("{:b}", p),
+            _ => format!("{:o}", p),
+        },
+        (Some(radix), _) => {
+            return Err(invalid(
+                template,
+                &format!("`{}` needs a whole number below 2^64 but got {:?}", radix, value),
+            ))
+        }
+        (None, StackType::Float(n)) => match spec.precision {
+            Some(precision) => format!("{:.*}", precision, n),
+            None => n.to_string(),
+        },
+        (None, StackType::String(s)) => match spec.precision {
+            Some(precision) => s.chars().take(precision).collect(),
+            None => s.clone(),
+        },
+        (None, value) => value.to_string(),
+    };
+
+    let len = text.chars().count();
+    if len >= spec.width {
+        return Ok(text);
+    }
+    let pad = spec.width - len;
+
+    // Zeros go between the sign and the digits
+    if spec.zero && numeric && spec.align.is_none() {
+        let (sign, digits) = match text.strip_prefix('-') {
+            Some(digits) => ("-", digits),
+            None => ("", text.as_str()),
+        };
+        return Ok(format!("{}{}{}", sign, "0".repeat(pad), digits));
+    }
+
+    let fill = spec.fill.unwrap_or(' ').to_string();
+    let align = spec
+        .align
+        .unwrap_or(if numeric { Align::Right } else { Align::Left });
+
+    Ok(match align {
+        Align::Left => format!("{}{}", text, fill.repeat(pad)),
+        Align::Right => format!("{}{}", fill.repeat(pad), text),
+        Align::Center => format!("{}{}{}", fill.repeat(pad / 2), text, fill.repeat(pad - pad / 2)),
+    })
+}
+
+/// Fills the placeholders of the template with `values` in order
+pub fn format(template: &str, values: &[StackType]) -> Result<String, Error> {
+    let mut values = values.iter();
+    let mut result = String::new();
+
+    for part in parse(template)? {
+        match part {
+            Part::Text(text) => result.push_str(&text),
+            Part::Value(spec) => match values.next() {
+                Some(v) => result.push_str(&value(template, &spec, v)?),
+                None => return Err(invalid(template, "more placeholders than values")),
+            },
+        }
+    }
+
+    Ok(result)
+}
@@ -0,0 +1,196 @@
+use crate::{env::EnvRef, error::Error, interpreter::StackType};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+
+/// Live entries needed before the first automatic collection
+const MIN_GC_THRESHOLD: usize = 64;
+
+#[derive(Debug)]
+pub struct ObjectData {
+    pub properties: HashMap<String, StackType>,
+}
+
+/// Key of a map, maps can be keyed by strings and numbers
+#[derive(Debug, Clone)]
+pub enum MapKey {
+    Number(f64),
+    String(String),
+}
+
+impl MapKey {
+    pub fn from_value(value: StackType) -> Option<Self> {
+        match value {
+            // -0 and 0 are the same key
+            StackType::Float(f) => Some(Self::Number(if f == 0.0 { 0.0 } else { f })),
+            StackType::String(s) => Some(Self::String(s)),
+            _ => None,
+        }
+    }
+
+    pub fn to_value(&self) -> StackType {
+        match self {
+            Self::Number(f) => StackType::Float(*f),
+            Self::String(s) => StackType::String(s.to_owned()),
+        }
+    }
+}
+
+impl fmt::Display for MapKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Number(n) => write!(f, "{}", n),
+            Self::String(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+// Numbers sort before strings
+impl Ord for MapKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Self::Number(a), Self::Number(b)) => a.total_cmp(b),
+            (Self::String(a), Self::String(b)) => a.cmp(b),
+            (Self::Number(_), Self::String(_)) => Ordering::Less,
+            (Self::String(_), Self::Number(_)) => Ordering::Greater,
+        }
+    }
+}
+
+impl PartialOrd for MapKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for MapKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for MapKey {}
+
+/// Heap data type
+#[derive(Debug)]
+pub enum HeapData {
+    Object(ObjectData),
+    Map(BTreeMap<MapKey, StackType>),
+}
+
+impl HeapData {
+    /// Values this entry holds, these keep other entries alive
+    fn children(&self) -> Vec<&StackType> {
+        match self {
+            Self::Object(obj) => obj.properties.values().collect(),
+            Self::Map(map) => map.values().collect(),
+        }
+    }
+}
+
+/// Heap with a mark-and-sweep collector
+///
+/// Entries never move: a freed slot is left empty and reused by a later
+/// allocation, so a `StackType::Pointer` stays valid for as long as the
+/// entry is reachable
+pub struct Heap {
+    slots: Vec<Option<HeapData>>,
+    free: Vec<usize>,
+    next_gc: usize,
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            free: Vec::new(),
+            next_gc: MIN_GC_THRESHOLD,
+        }
+    }
+
+    /// Number of live entries
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    /// True when the heap grew enough since the last collection
+    pub fn should_collect(&self) -> bool {
+        self.len() >= self.next_gc
+    }
+
+    pub fn alloc(&mut self, data: HeapData) -> usize {
+        match self.free.pop() {
+            Some(ptr) => {
+                self.slots[ptr] = Some(data);
+                ptr
+            }
+            None => {
+                self.slots.push(Some(data));
+                self.slots.len() - 1
+            }
+        }
+    }
+
+    pub fn get(&self, ptr: usize) -> Result<&HeapData, Error> {
+        match self.slots.get(ptr) {
+            Some(Some(data)) => Ok(data),
+            _ => Err(Error::DanglingPointer(ptr)),
+        }
+    }
+
+    pub fn get_mut(&mut self, ptr: usize) -> Result<&mut HeapData, Error> {
+        match self.slots.get_mut(ptr) {
+            Some(Some(data)) => Ok(data),
+            _ => Err(Error::DanglingPointer(ptr)),
+        }
+    }
+
+    /// Frees every entry that can't be reached from `roots` or the lets of
+    /// `envs`, returns the number of freed entries
+    pub fn collect(&mut self, roots: Vec<StackType>, envs: Vec<EnvRef>) -> usize {
+        let mut marked = vec![false; self.slots.len()];
+        let mut visited_envs = HashSet::new();
+        let mut pending = roots;
+        let mut pending_envs = envs;
+
+        // Mark
+        loop {
+            if let Some(value) = pending.pop() {
+                match value {
+                    StackType::Pointer(p) => {
+                        if let Some(Some(data)) = self.slots.get(p) {
+                            if !marked[p] {
+                                marked[p] = true;
+                                pending.extend(data.children().into_iter().cloned());
+                            }
+                        }
+                    }
+                    StackType::Array(items) => pending.extend(items),
+                    StackType::Quote(closure) => pending_envs.push(closure.env),
+                    _ => {}
+                }
+            } else if let Some(env) = pending_envs.pop() {
+                if visited_envs.insert(env.id()) {
+                    let env = env.borrow();
+                    pending.extend(env.lets.iter().map(|l| l.value.clone()));
+                    pending_envs.extend(env.parent.clone());
+                }
+            } else {
+                break;
+            }
+        }
+
+        // Sweep
+        let mut freed = 0;
+        for (ptr, slot) in self.slots.iter_mut().enumerate() {
+            if slot.is_some() && !marked[ptr] {
+                *slot = None;
+                self.free.push(ptr);
+                freed += 1;
+            }
+        }
+
+        self.next_gc = (self.len() * 2).max(MIN_GC_THRESHOLD);
+        freed
+    }
+}
@@ -1,22 +1,36 @@
-use std::collections::HashMap;
-use crate::{token::Token, types::*};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use crate::{
+    env::{Closure, EnvRef},
+    error::Error,
+    format::{format, placeholders},
+    heap::{Heap, HeapData, MapKey, ObjectData},
+    json,
+    random::Rng,
+    suggest::suggest,
+    time::iso8601,
+    token::Token,
+    types::*,
+};
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
 use std::ops::{Add, Div, Mul, Rem, Sub};
+use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+#[allow(dead_code)]
 trait Size {
     fn get_size(&self) -> usize;
 }
 
-pub enum MemoryScope {
-    Function,
-    Global,
-}
-
 #[derive(Debug, PartialEq, PartialOrd, Clone)]
 pub enum StackType {
     Pointer(usize),
     Float(f64),
     String(String),
     Array(Vec<StackType>),
+    /// A block of code pushed as a value, with the env it was created in
+    Quote(Closure),
 }
 
 impl Size for StackType {
@@ -26,415 +40,1411 @@ impl Size for StackType {
             StackType::Pointer(p) => *p,
             StackType::Array(vec) => vec.len(),
             StackType::String(string) => string.len(),
+            StackType::Quote(closure) => closure.body.len(),
         }
     }
 }
 
-impl StackType {
-    fn print(self) {
+/// Shows the value the way `put` and `print` write it
+impl fmt::Display for StackType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::Float(f) => println!("{}", f),
-            Self::Pointer(p) => println!("0x{:x}", p),
-            Self::String(str) => println!("{}", str),
-            Self::Array(vec) => println!("{:?}", vec),
+            Self::Float(n) => write!(f, "{}", n),
+            Self::Pointer(p) => write!(f, "0x{:x}", p),
+            Self::String(str) => write!(f, "{}", str),
+            Self::Array(vec) => write!(f, "{:?}", vec),
+            Self::Quote(_) => write!(f, "<quote>"),
         }
     }
 }
 
+/// Applies an arithmetic keyword to two numbers or two pointers
+fn arithmetic(
+    keyword: &'static str,
+    a: StackType,
+    b: StackType,
+    float: fn(f64, f64) -> f64,
+    pointer: fn(usize, usize) -> Option<usize>,
+) -> Result<StackType, Error> {
+    match (a, b) {
+        (StackType::Float(a), StackType::Float(b)) => Ok(StackType::Float(float(a, b))),
+        (StackType::Pointer(a), StackType::Pointer(b)) => match pointer(a, b) {
+            Some(p) => Ok(StackType::Pointer(p)),
+            None => Err(Error::InvalidOperands(keyword, StackType::Pointer(a), StackType::Pointer(b))),
+        },
+        (a, b) => Err(Error::InvalidOperands(keyword, a, b)),
+    }
+}
+
 impl Add for StackType {
-    type Output = Self;
+    type Output = Result<Self, Error>;
     fn add(self, rhs: Self) -> Self::Output {
-        match self {
-            Self::Float(f) => match rhs {
-                Self::Float(rf) => Self::Float(f + rf),
-                _ => panic!("Can't Add not float type to float"),
-            },
-            Self::Pointer(p) => match rhs {
-                Self::Pointer(rp) => Self::Pointer(p + rp),
-                _ => panic!("Can't Add not pointer to pointer"),
-            },
-            _ => panic!("Can't Add"),
-        }
+        arithmetic("add", self, rhs, |a, b| a + b, usize::checked_add)
     }
 }
 
 impl Sub for StackType {
-    type Output = Self;
+    type Output = Result<Self, Error>;
     fn sub(self, rhs: Self) -> Self::Output {
-        match self {
-            Self::Float(f) => match rhs {
-                Self::Float(rf) => Self::Float(f - rf),
-                _ => panic!("Can't Subtract not float to float"),
-            },
-
-            Self::Pointer(p) => match rhs {
-                Self::Pointer(rp) => Self::Pointer(p - rp),
-                _ => panic!("Can't Subtract not pointer to pointer"),
-            },
-            _ => panic!("Can't Subtract"),
-        }
+        arithmetic("minus", self, rhs, |a, b| a - b, usize::checked_sub)
     }
 }
 
 impl Div for StackType {
-    type Output = Self;
+    type Output = Result<Self, Error>;
     fn div(self, rhs: Self) -> Self::Output {
-        match self {
-            Self::Float(f) => match rhs {
-                Self::Float(rf) => Self::Float(f / rf),
-                _ => panic!("Can't Divide not float to float"),
-            },
-
-            Self::Pointer(p) => match rhs {
-                Self::Pointer(rp) => Self::Pointer(p / rp),
-                _ => panic!("Can't Divide not pointer to pointer"),
-            },
-
-            _ => panic!("Can't Divide"),
-        }
+        arithmetic("div", self, rhs, |a, b| a / b, usize::checked_div)
     }
 }
 
 impl Mul for StackType {
-    type Output = Self;
+    type Output = Result<Self, Error>;
     fn mul(self, rhs: Self) -> Self::Output {
-        match self {
-            Self::Float(f) => match rhs {
-                Self::Float(rf) => Self::Float(f * rf),
-                _ => panic!("Can't Multiply not float to float"),
-            },
-
-
-            Self::Pointer(p) => match rhs {
-                Self::Pointer(rp) => Self::Pointer(p * rp),
-                _ => panic!("Can't Multiply not pointer to pointer"),
-            },
-            _ => panic!("Can't Multiply"),
-        }
+        arithmetic("mul", self, rhs, |a, b| a * b, usize::checked_mul)
     }
 }
 
 impl Rem for StackType {
-    type Output = Self;
+    type Output = Result<Self, Error>;
     fn rem(self, rhs: Self) -> Self::Output {
-        match self {
-            Self::Float(f) => match rhs {
-                Self::Float(rf) => Self::Float(f % rf),
-                _ => panic!("Can't Rem not float to float"),
-            },
+        arithmetic("mod", self, rhs, |a, b| a % b, usize::checked_rem)
+    }
+}
 
-            Self::Pointer(p) => match rhs {
-                Self::Pointer(rp) => Self::Pointer(p % rp),
-                _ => panic!("Can't Rem not pointer to pointer"),
-            },
-            _ => panic!("Can't Rem"),
-        }
+/// Greatest common divisor of two whole numbers
+fn gcd(a: f64, b: f64) -> Result<f64, Error> {
+    if a.fract() != 0.0 || b.fract() != 0.0 || !a.is_finite() || !b.is_finite() {
+        return Err(Error::Domain("gcd", format!("{} and {}", a, b)));
     }
+
+    let (mut a, mut b) = (a.abs(), b.abs());
+    while b != 0.0 {
+        (a, b) = (b, a % b);
+    }
+
+    Ok(a)
 }
 
-#[derive(Debug)]
-pub struct ObjectData {
-    properties: HashMap<String, Token>,
+/// Error of a file keyword, with the path it was used on
+fn file_error(path: &str, e: io::Error) -> Error {
+    Error::Io(format!("{}: {}", path, e))
 }
 
-/// Heap data type
-#[derive(Debug)]
-pub enum HeapData {
-    Object(ObjectData),
+/// What to do when a frame runs out of tokens
+enum FrameKind {
+    /// Body of a quote, `then` or the top level, nothing
+    Block,
+    /// Body of a function, check the declared result types if any
+    Call(String, Option<Vec<Type>>),
+    /// Body of `times`, run it again until no runs are left
+    Times { body: Closure, remaining: u32 },
+    /// Body of `try`, errors inside it run the handler with the stack cut
+    /// back to `depth`
+    Try { handler: Closure, depth: usize },
+}
+
+/// A block of tokens being run
+struct Frame {
+    tokens: Rc<[Token]>,
+    /// Index of the next token to run
+    pc: usize,
+    env: EnvRef,
+    kind: FrameKind,
+}
+
+impl Frame {
+    /// True when the frame ran all its tokens and ending it does nothing
+    fn is_done(&self) -> bool {
+        let nothing_to_do = match &self.kind {
+            FrameKind::Block => true,
+            FrameKind::Call(_, results) => results.is_none(),
+            FrameKind::Times { remaining, .. } => *remaining == 0,
+            FrameKind::Try { .. } => false,
+        };
+
+        nothing_to_do && self.pc >= self.tokens.len()
+    }
+}
+
+/// What the file keywords are allowed to do
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Default)]
+pub enum FileAccess {
+    /// No file keyword can be used
+    #[default]
+    None,
+    /// `readfile`, `listdir` and `exists`
+    Read,
+    /// Every file keyword, including the ones that write and delete
+    ReadWrite,
 }
 
 pub struct Interpreter {
-    pub heap: Vec<HeapData>,
+    pub heap: Heap,
+    /// Where `put` and `print` write, stdout unless the host gives another
+    pub output: Box<dyn Write>,
+    /// Where `readline`, `readnum` and `readall` read, stdin unless the host
+    /// gives another
+    pub input: Box<dyn BufRead>,
+    /// Sandbox of the file keywords, no access unless the host allows it
+    pub files: FileAccess,
+    /// Arguments given to the program, pushed by `args`
+    pub args: Vec<String>,
+    /// Numbers of `rand`, `randint` and `shuffle`, seeded from the clock
+    /// unless the program or the host calls `seed`
+    pub rng: Rng,
+    /// When the interpreter was made, `now_ms` counts from it
+    start: Instant,
     pub stack: Vec<StackType>,
-    pub memory: Vec<Let>,
-    pub functions: Vec<Function>,
-    pub mem_scope: MemoryScope,
-    pub function_time: usize,
+    /// Values below this depth belong to the code around the array literal
+    /// being built, they can't be popped
+    floor: usize,
+    /// Lets defined at the top level of the program
+    pub globals: EnvRef,
+    /// Env of the code running now, nested in the env it was defined in
+    pub env: EnvRef,
+    /// Frames being run, innermost last
+    frames: Vec<Frame>,
+    /// Number of frames that belong to outer `execute` loops
+    base: usize,
+    /// Where the error being unwound happened
+    error_location: Option<String>,
+    /// Tests declared so far with `test name { }`, they only run when the
+    /// test runner asks for them
+    pub tests: Vec<(String, Closure)>,
 }
 
 impl Interpreter {
     pub fn new() -> Self {
+        let globals = EnvRef::default();
+
         Self {
-            heap: Vec::new(),
+            heap: Heap::new(),
+            output: Box::new(io::stdout()),
+            input: Box::new(io::BufReader::new(io::stdin())),
+            files: FileAccess::None,
+            args: vec![],
+            rng: Rng::from_time(),
+            start: Instant::now(),
             stack: Vec::with_capacity(255),
-            memory: vec![],
-            functions: vec![],
-            mem_scope: MemoryScope::Global,
-            function_time: 0,
+            floor: 0,
+            globals: globals.clone(),
+            env: globals,
+            frames: vec![],
+            base: 0,
+            error_location: None,
+            tests: vec![],
         }
     }
 
-    pub fn parse(&mut self, tokens: Vec<Token>) {
-        let mut iter = tokens.iter();
+    /// Interpreter that writes its output to `output`
+    pub fn with_output(output: Box<dyn Write>) -> Self {
+        Self {
+            output,
+            ..Self::new()
+        }
+    }
 
-        while let Some(token) = iter.next() {
-            //println!("{:?}", token);
-            match token {
-                Token::Number(n) => {
-                    self.stack.push(StackType::Float(n.to_owned()));
-                }
-                Token::Add => {
-                    let push = self.stack.pop().unwrap() + self.stack.pop().unwrap();
-                    self.stack.push(push);
+    /// Error for a name that isn't a let in scope, suggests the closest let
+    /// or function
+    fn undefined_let(&self, name: &str) -> Error {
+        let lets = self.env.names();
+        let functions = self.env.function_names();
+
+        let suggestion = match suggest(name, lets.iter().map(String::as_str)) {
+            Some(l) => Some(l),
+            None => suggest(name, functions.iter().map(String::as_str)).map(|f| format!("call {}", f)),
+        };
+
+        Error::UndefinedLet(name.to_string(), suggestion)
+    }
+
+    /// Error for a call to a name that is not a function or a let in scope
+    fn undefined_function(&self, name: &str) -> Error {
+        let lets = self.env.names();
+        let functions = self.env.function_names();
+        let names = functions.iter().chain(lets.iter()).map(String::as_str);
+
+        Error::UndefinedFunction(name.to_string(), suggest(name, names))
+    }
+
+    /// Checks the values a function left on top of stack against its
+    /// declared result types
+    fn check_results(&self, function: &str, results: &[Type]) -> Result<(), Error> {
+        let base = self.stack.len() as isize - results.len() as isize;
+
+        for (i, ty) in results.iter().enumerate() {
+            let value = usize::try_from(base + i as isize).ok().and_then(|i| self.stack.get(i));
+
+            match value {
+                Some(value) if ty.accepts(Type::of(value)) => {}
+                _ => return Err(Error::ResultType(function.to_string(), i, *ty, value.cloned())),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads the next line of input without its line break, `None` at the
+    /// end of input. What was printed so far is written first, so prompts
+    /// show up before the program waits
+    fn read_line(&mut self) -> Result<Option<String>, Error> {
+        self.output.flush()?;
+
+        let mut line = String::new();
+        if self.input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        if line.ends_with('\n') {
+            line.pop();
+            if line.ends_with('\r') {
+                line.pop();
+            }
+        }
+
+        Ok(Some(line))
+    }
+
+    /// Pops the top of stack
+    fn pop(&mut self, keyword: &str) -> Result<StackType, Error> {
+        self.need(keyword, 1)?;
+        Ok(self.stack.pop().unwrap())
+    }
+
+    /// Pops a quote from stack
+    fn pop_quote(&mut self, keyword: &str) -> Result<Closure, Error> {
+        match self.pop(keyword)? {
+            StackType::Quote(closure) => Ok(closure),
+            _ => Err(Error::ExpectedType(keyword.to_string(), "quote")),
+        }
+    }
+
+    /// Makes `frame` the one that runs next
+    ///
+    /// Frames that have run all their tokens and have nothing to do when
+    /// they end are dropped first, so a call in tail position takes the
+    /// place of its caller instead of growing the frame stack
+    fn push_frame(&mut self, frame: Frame) {
+        while self.frames.len() > self.base && self.frames.last().is_some_and(Frame::is_done) {
+            self.frames.pop();
+        }
+
+        self.env = frame.env.clone();
+        self.frames.push(frame);
+    }
+
+    /// Runs a quote next, it gets a fresh env nested in the one it was
+    /// created in
+    fn push_closure(&mut self, closure: Closure) {
+        self.push_frame(Frame {
+            tokens: closure.body,
+            pc: 0,
+            env: closure.env.child(),
+            kind: FrameKind::Block,
+        });
+    }
+
+    /// Sets the current env to the env of the innermost frame
+    fn restore_env(&mut self) {
+        self.env = match self.frames.last() {
+            Some(frame) => frame.env.clone(),
+            None => self.globals.clone(),
+        };
+    }
+
+    /// Called when the innermost frame ran all its tokens
+    fn end_frame(&mut self) -> Result<(), Error> {
+        let frame = self.frames.last_mut().unwrap();
+
+        // Run the body of times again with a fresh env
+        if let FrameKind::Times { body, remaining } = &mut frame.kind {
+            if *remaining > 0 {
+                *remaining -= 1;
+                frame.pc = 0;
+                frame.env = body.env.child();
+                self.env = frame.env.clone();
+                return Ok(());
+            }
+        }
+
+        if let Some(FrameKind::Call(name, Some(results))) = self.frames.last().map(|f| &f.kind) {
+            self.check_results(name, results)?;
+        }
+
+        self.frames.pop();
+        self.restore_env();
+
+        Ok(())
+    }
+
+    /// Takes the scope right after the token being run, if there is one
+    fn next_scope(&mut self) -> Option<Rc<[Token]>> {
+        let frame = self.frames.last_mut()?;
+
+        match frame.tokens.get(frame.pc) {
+            Some(Token::Scope(tokens)) => {
+                let tokens = tokens.clone();
+                frame.pc += 1;
+                Some(tokens)
+            }
+            _ => None,
+        }
+    }
+
+    /// Pops a template and the values for its placeholders, the deepest value
+    /// fills the first placeholder
+    fn format(&mut self, keyword: &str) -> Result<String, Error> {
+        let template = self.pop_string(keyword)?;
+        let n = placeholders(&template)?;
+        self.need(keyword, n)?;
+
+        let values = self.stack.split_off(self.stack.len() - n);
+        format(&template, &values)
+    }
+
+    /// Pops a number, applies `f` and pushes the result, `domain` says which
+    /// numbers the keyword is defined for
+    fn unary(&mut self, keyword: &'static str, domain: fn(f64) -> bool, f: fn(f64) -> f64) -> Result<(), Error> {
+        let n = match self.pop(keyword)? {
+            StackType::Float(n) => n,
+            _ => return Err(Error::ExpectedType(keyword.to_string(), "number")),
+        };
+        if !domain(n) {
+            return Err(Error::Domain(keyword, n.to_string()));
+        }
+
+        self.stack.push(StackType::Float(f(n)));
+        Ok(())
+    }
+
+    /// Pops two numbers and pushes `f` of them, the top of stack is the first
+    fn binary(&mut self, keyword: &'static str, f: fn(f64, f64) -> Result<f64, Error>) -> Result<(), Error> {
+        let push = match (self.pop(keyword)?, self.pop(keyword)?) {
+            (StackType::Float(a), StackType::Float(b)) => f(a, b)?,
+            (a, b) => return Err(Error::InvalidOperands(keyword, a, b)),
+        };
+
+        self.stack.push(StackType::Float(push));
+        Ok(())
+    }
+
+    /// Fails unless the stack has at least `n` values above the floor
+    fn need(&self, keyword: &str, n: usize) -> Result<(), Error> {
+        if self.stack.len() - self.floor < n {
+            return Err(Error::StackUnderflow(keyword.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Pops the position of a value from the top of stack, 0 is the top. The
+    /// stack must have a value there
+    fn pop_index(&mut self, keyword: &'static str) -> Result<usize, Error> {
+        match self.pop(keyword)? {
+            // Compared as a float, a huge count would saturate as usize
+            StackType::Float(n) if n.fract() == 0.0 && n >= 0.0 => {
+                if n >= (self.stack.len() - self.floor) as f64 {
+                    return Err(Error::StackUnderflow(keyword.to_string()));
                 }
-                Token::Minus => {
-                    let push = self.stack.pop().unwrap() - self.stack.pop().unwrap();
-                    self.stack.push(push);
+                Ok(n as usize)
+            }
+            StackType::Float(n) => Err(Error::Domain(keyword, n.to_string())),
+            _ => Err(Error::ExpectedType(keyword.to_string(), "number")),
+        }
+    }
+
+    /// Pops a string from stack
+    fn pop_string(&mut self, keyword: &str) -> Result<String, Error> {
+        match self.pop(keyword)? {
+            StackType::String(string) => Ok(string),
+            _ => Err(Error::ExpectedType(keyword.to_string(), "string")),
+        }
+    }
+
+    /// Pops a path from stack, if the sandbox gives `access` to files
+    fn pop_path(&mut self, keyword: &str, access: FileAccess) -> Result<String, Error> {
+        if self.files < access {
+            return Err(Error::PermissionDenied(keyword.to_string()));
+        }
+
+        self.pop_string(keyword)
+    }
+
+    /// Pops an array from stack
+    fn pop_array(&mut self, keyword: &str) -> Result<Vec<StackType>, Error> {
+        match self.pop(keyword)? {
+            StackType::Array(items) => Ok(items),
+            _ => Err(Error::ExpectedType(keyword.to_string(), "array")),
+        }
+    }
+
+    /// Returns the body following `then`/`times`, or the quote on top of
+    /// stack when it isn't followed by a scope. Every run of the body gets a
+    /// fresh env nested in the env of the returned closure
+    fn next_body(&mut self, keyword: &str) -> Result<Closure, Error> {
+        match self.next_scope() {
+            Some(tokens) => Ok(Closure {
+                body: tokens,
+                env: self.env.clone(),
+            }),
+            None => self.pop_quote(keyword),
+        }
+    }
+
+    /// Pops a pointer from stack and returns the heap data it points to
+    fn pop_heap(&mut self, keyword: &str) -> Result<&mut HeapData, Error> {
+        match self.pop(keyword)? {
+            StackType::Pointer(ptr) => self.heap.get_mut(ptr),
+            _ => Err(Error::ExpectedPointer(keyword.to_string())),
+        }
+    }
+
+    /// Pops a pointer from stack and returns the object it points to
+    fn pop_object(&mut self, keyword: &str) -> Result<&mut ObjectData, Error> {
+        match self.pop_heap(keyword)? {
+            HeapData::Object(obj) => Ok(obj),
+            _ => Err(Error::WrongHeapData(keyword.to_string(), "object")),
+        }
+    }
+
+    /// Pops a pointer from stack and returns the map it points to
+    fn pop_map(&mut self, keyword: &str) -> Result<&mut BTreeMap<MapKey, StackType>, Error> {
+        match self.pop_heap(keyword)? {
+            HeapData::Map(map) => Ok(map),
+            _ => Err(Error::WrongHeapData(keyword.to_string(), "map")),
+        }
+    }
+
+    /// Pops a map key from stack
+    fn pop_key(&mut self, keyword: &str) -> Result<MapKey, Error> {
+        MapKey::from_value(self.pop(keyword)?)
+            .ok_or_else(|| Error::InvalidKey(keyword.to_string()))
+    }
+
+    /// Moves data to heap and returns the pointer to it, collects garbage
+    /// first when the heap has grown enough
+    fn alloc(&mut self, data: HeapData) -> usize {
+        let ptr = self.heap.alloc(data);
+
+        if self.heap.should_collect() {
+            self.collect_garbage(&[StackType::Pointer(ptr)]);
+        }
+
+        ptr
+    }
+
+    /// Frees the heap entries that are not reachable from the stack, the
+    /// envs of running frames and tests, or `extra_roots`
+    pub fn collect_garbage(&mut self, extra_roots: &[StackType]) -> usize {
+        let roots = self.stack.iter().chain(extra_roots).cloned().collect();
+        let frame_envs = self.frames.iter().flat_map(|f| match &f.kind {
+            FrameKind::Times { body, .. } => vec![f.env.clone(), body.env.clone()],
+            FrameKind::Try { handler, .. } => vec![f.env.clone(), handler.env.clone()],
+            _ => vec![f.env.clone()],
+        });
+        let envs = [self.env.clone(), self.globals.clone()]
+            .into_iter()
+            .chain(frame_envs)
+            .chain(self.tests.iter().map(|(_, test)| test.env.clone()))
+            .collect();
+
+        self.heap.collect(roots, envs)
+    }
+
+    /// Runs tokens in the current env
+    pub fn parse(&mut self, tokens: impl Into<Rc<[Token]>>) -> Result<(), Error> {
+        self.execute(Frame {
+            tokens: tokens.into(),
+            pc: 0,
+            env: self.env.clone(),
+            kind: FrameKind::Block,
+        })
+    }
+
+    /// Runs a quote in a fresh env nested in the one it was created in
+    pub fn run_closure(&mut self, closure: Closure) -> Result<(), Error> {
+        self.execute(Frame {
+            tokens: closure.body,
+            pc: 0,
+            env: closure.env.child(),
+            kind: FrameKind::Block,
+        })
+    }
+
+    /// Where the last error that stopped the program happened
+    pub fn error_location(&self) -> Option<&str> {
+        self.error_location.as_deref()
+    }
+
+    /// Runs a frame and everything it pushes until it ends
+    ///
+    /// Calls, quotes and the bodies of `then`/`times` push frames to
+    /// `self.frames` instead of recursing, only literals that need the
+    /// values their body leaves (arrays, objects) and imports run a nested
+    /// loop
+    fn execute(&mut self, frame: Frame) -> Result<(), Error> {
+        let base = self.frames.len();
+        if base == 0 {
+            self.error_location = None;
+        }
+        let outer_base = std::mem::replace(&mut self.base, base);
+        self.push_frame(frame);
+
+        let mut result = Ok(());
+        while self.frames.len() > base {
+            let frame = self.frames.last_mut().unwrap();
+            let tokens = Rc::clone(&frame.tokens);
+            let pc = frame.pc;
+
+            let token = tokens.get(pc);
+            result = match token {
+                Some(token) => {
+                    frame.pc += 1;
+                    self.step(token)
                 }
-                Token::Div => {
-                    let push = self.stack.pop().unwrap() / self.stack.pop().unwrap();
-                    self.stack.push(push);
+                None => self.end_frame(),
+            };
+
+            if let Err(error) = result {
+                if self.error_location.is_none() {
+                    self.error_location = Some(self.location(token));
                 }
-                Token::Mul => {
-                    let push = self.stack.pop().unwrap() * self.stack.pop().unwrap();
-                    self.stack.push(push);
+
+                result = self.catch(base, error);
+                if result.is_err() {
+                    self.frames.truncate(base);
+                    self.restore_env();
+                    break;
                 }
-                Token::Mod => {
-                    let push = self.stack.pop().unwrap() % self.stack.pop().unwrap();
-                    self.stack.push(push);
+            }
+        }
+
+        self.base = outer_base;
+        result
+    }
+
+    /// Describes where the innermost frame is, `token` is the one it failed at
+    fn location(&self, token: Option<&Token>) -> String {
+        let function = self.frames.iter().rev().find_map(|f| match &f.kind {
+            FrameKind::Call(name, _) => Some(name),
+            _ => None,
+        });
+
+        match (token, function) {
+            (Some(token), Some(function)) => format!("`{}` in fn {}", token, function),
+            (Some(token), None) => format!("`{}`", token),
+            (None, Some(function)) => format!("end of fn {}", function),
+            (None, None) => "end of program".to_string(),
+        }
+    }
+
+    /// Unwinds to the innermost try above `base` and runs its handler with
+    /// the error on top of stack, gives the error back if there is no try
+    fn catch(&mut self, base: usize, error: Error) -> Result<(), Error> {
+        let try_frame = self.frames[base..]
+            .iter()
+            .rposition(|f| matches!(f.kind, FrameKind::Try { .. }));
+        let Some(i) = try_frame.filter(|_| !matches!(error, Error::Exit(_))) else {
+            return Err(error);
+        };
+
+        self.frames.truncate(base + i + 1);
+        let Some(FrameKind::Try { handler, depth }) = self.frames.pop().map(|f| f.kind) else {
+            unreachable!()
+        };
+        self.stack.truncate(depth);
+
+        // The handler's frame goes first so its env is a root while the
+        // error object is allocated, the frame that made the handler may
+        // be gone already
+        self.push_closure(handler);
+
+        let location = self.error_location.take().unwrap_or_default();
+        let value = match error {
+            // Thrown values are caught as they are
+            Error::Thrown(value) => value,
+            error => {
+                let properties = [
+                    ("message", error.to_string()),
+                    ("kind", error.kind().to_string()),
+                    ("location", location),
+                ];
+                let ptr = self.alloc(HeapData::Object(ObjectData {
+                    properties: properties
+                        .into_iter()
+                        .map(|(name, value)| (name.to_string(), StackType::String(value)))
+                        .collect(),
+                }));
+
+                StackType::Pointer(ptr)
+            }
+        };
+
+        self.stack.push(value);
+
+        Ok(())
+    }
+
+    fn step(&mut self, token: &Token) -> Result<(), Error> {
+        match token {
+            Token::Number(n) => {
+                self.stack.push(StackType::Float(n.to_owned()));
+            }
+            Token::Add => {
+                let push = (self.pop("add")? + self.pop("add")?)?;
+                self.stack.push(push);
+            }
+            Token::Minus => {
+                let push = (self.pop("minus")? - self.pop("minus")?)?;
+                self.stack.push(push);
+            }
+            Token::Div => {
+                let push = (self.pop("div")? / self.pop("div")?)?;
+                self.stack.push(push);
+            }
+            Token::Mul => {
+                let push = (self.pop("mul")? * self.pop("mul")?)?;
+                self.stack.push(push);
+            }
+            Token::Mod => {
+                let push = (self.pop("mod")? % self.pop("mod")?)?;
+                self.stack.push(push);
+            }
+            Token::Sqrt => self.unary("sqrt", |n| n >= 0.0, f64::sqrt)?,
+            Token::Abs => self.unary("abs", |_| true, f64::abs)?,
+            Token::Floor => self.unary("floor", |_| true, f64::floor)?,
+            Token::Ceil => self.unary("ceil", |_| true, f64::ceil)?,
+            Token::Round => self.unary("round", |_| true, f64::round)?,
+            Token::Sin => self.unary("sin", |_| true, f64::sin)?,
+            Token::Cos => self.unary("cos", |_| true, f64::cos)?,
+            Token::Tan => self.unary("tan", |_| true, f64::tan)?,
+            Token::Log => self.unary("log", |n| n > 0.0, f64::ln)?,
+            Token::Exp => self.unary("exp", |_| true, f64::exp)?,
+            Token::Pow => self.binary("pow", |a, b| match a.powf(b) {
+                n if n.is_nan() && !a.is_nan() && !b.is_nan() => Err(Error::Domain("pow", format!("{} and {}", a, b))),
+                n => Ok(n),
+            })?,
+            Token::Min => self.binary("min", |a, b| Ok(a.min(b)))?,
+            Token::Max => self.binary("max", |a, b| Ok(a.max(b)))?,
+            Token::Gcd => self.binary("gcd", gcd)?,
+            Token::Pi => self.stack.push(StackType::Float(std::f64::consts::PI)),
+            Token::E => self.stack.push(StackType::Float(std::f64::consts::E)),
+            Token::Rand => {
+                let n = self.rng.next_f64();
+                self.stack.push(StackType::Float(n));
+            }
+            // Whole number between the two, both included
+            Token::RandInt => {
+                let (hi, lo) = match (self.pop("randint")?, self.pop("randint")?) {
+                    (StackType::Float(hi), StackType::Float(lo)) => (hi, lo),
+                    (a, b) => return Err(Error::InvalidOperands("randint", a, b)),
+                };
+                if lo.fract() != 0.0 || hi.fract() != 0.0 || lo > hi || hi - lo >= u64::MAX as f64 {
+                    return Err(Error::Domain("randint", format!("{} and {}", lo, hi)));
                 }
-                Token::Swap => {
-                    let i1 = self.stack.pop().unwrap();
-                    let i2 = self.stack.pop().unwrap();
 
-                    self.stack.push(i1);
-                    self.stack.push(i2);
+                let n = lo + self.rng.below((hi - lo) as u64 + 1) as f64;
+                self.stack.push(StackType::Float(n));
+            }
+            Token::Shuffle => match self.pop("shuffle")? {
+                StackType::Array(mut vec) => {
+                    for i in (1..vec.len()).rev() {
+                        let j = self.rng.below(i as u64 + 1) as usize;
+                        vec.swap(i, j);
+                    }
+                    self.stack.push(StackType::Array(vec));
                 }
-                Token::Rot => {
-                    let i1 = self.stack.pop().unwrap();
-                    let i2 = self.stack.pop().unwrap();
-                    let i3 = self.stack.pop().unwrap();
-
-                    self.stack.push(i1);
-                    self.stack.push(i2);
-                    self.stack.push(i3);
+                _ => return Err(Error::ExpectedType("shuffle".to_string(), "array")),
+            },
+            Token::Seed => match self.pop("seed")? {
+                StackType::Float(n) if n.fract() == 0.0 && n >= 0.0 && n < u64::MAX as f64 => {
+                    self.rng = Rng::new(n as u64);
                 }
-                Token::Put => self.stack.pop().unwrap().print(),
-                Token::Eq => {
-                    // Pop items from stack
-                    let b = self.stack.pop().unwrap() == self.stack.pop().unwrap();
-                    let res = if b == true { 1.0 } else { 0.0 };
-                    self.stack.push(StackType::Float(res));
+                StackType::Float(n) => return Err(Error::Domain("seed", n.to_string())),
+                _ => return Err(Error::ExpectedType("seed".to_string(), "number")),
+            },
+            // Milliseconds on a clock that never goes back
+            Token::NowMs => {
+                let ms = self.start.elapsed().as_secs_f64() * 1000.0;
+                self.stack.push(StackType::Float(ms));
+            }
+            // Seconds since 1970-01-01 UTC
+            Token::UnixTime => {
+                let seconds = match SystemTime::now().duration_since(UNIX_EPOCH) {
+                    Ok(d) => d.as_secs_f64(),
+                    Err(e) => -e.duration().as_secs_f64(),
+                };
+                self.stack.push(StackType::Float(seconds));
+            }
+            Token::Sleep => match self.pop("sleep")? {
+                StackType::Float(ms) => match Duration::try_from_secs_f64(ms / 1000.0) {
+                    Ok(duration) => {
+                        self.output.flush()?;
+                        std::thread::sleep(duration);
+                    }
+                    Err(_) => return Err(Error::Domain("sleep", ms.to_string())),
+                },
+                _ => return Err(Error::ExpectedType("sleep".to_string(), "number")),
+            },
+            Token::IsoTime => match self.pop("isotime")? {
+                StackType::Float(seconds) => match iso8601(seconds) {
+                    Some(text) => self.stack.push(StackType::String(text)),
+                    None => return Err(Error::Domain("isotime", seconds.to_string())),
+                },
+                _ => return Err(Error::ExpectedType("isotime".to_string(), "number")),
+            },
+            // Objects of the text are allocated straight on heap and only
+            // collected once the whole value is on stack
+            Token::JsonParse => {
+                let text = self.pop_string("json_parse")?;
+                let value = json::parse(&text)?.into_value(&mut self.heap);
+                self.stack.push(value);
+                if self.heap.should_collect() {
+                    self.collect_garbage(&[]);
                 }
+            }
+            Token::JsonStringify => {
+                let value = self.pop("json_stringify")?;
+                let text = json::stringify(&value, &self.heap)?;
+                self.stack.push(StackType::String(text));
+            }
+            Token::Swap => {
+                let i1 = self.pop("swap")?;
+                let i2 = self.pop("swap")?;
+
+                self.stack.push(i1);
+                self.stack.push(i2);
+            }
+            Token::Rot => {
+                let i1 = self.pop("rot")?;
+                let i2 = self.pop("rot")?;
+                let i3 = self.pop("rot")?;
+
+                self.stack.push(i1);
+                self.stack.push(i2);
+                self.stack.push(i3);
+            }
+            Token::Over => {
+                self.need("over", 2)?;
+                self.stack.push(self.stack[self.stack.len() - 2].clone());
+            }
+            Token::Nip => {
+                self.need("nip", 2)?;
+                self.stack.remove(self.stack.len() - 2);
+            }
+            Token::Tuck => {
+                self.need("tuck", 2)?;
+                let top = self.stack[self.stack.len() - 1].clone();
+                self.stack.insert(self.stack.len() - 2, top);
+            }
+            // Moves the top under the next two
+            Token::UnRot => {
+                self.need("-rot", 3)?;
+                let len = self.stack.len();
+                self.stack[len - 3..].rotate_right(1);
+            }
+            Token::TwoDup => {
+                self.need("2dup", 2)?;
+                self.stack.extend_from_within(self.stack.len() - 2..);
+            }
+            Token::TwoDrop => {
+                self.need("2drop", 2)?;
+                self.stack.truncate(self.stack.len() - 2);
+            }
+            Token::TwoSwap => {
+                self.need("2swap", 4)?;
+                let len = self.stack.len();
+                self.stack[len - 4..].rotate_left(2);
+            }
+            // Copies the value n below the top, `0 pick` is `dup`
+            Token::Pick => {
+                let n = self.pop_index("pick")?;
+                self.stack.push(self.stack[self.stack.len() - 1 - n].clone());
+            }
+            // Moves the value n below the top to the top, `1 roll` is `swap`
+            Token::Roll => {
+                let n = self.pop_index("roll")?;
+                let value = self.stack.remove(self.stack.len() - 1 - n);
+                self.stack.push(value);
+            }
+            Token::Depth => {
+                self.stack.push(StackType::Float((self.stack.len() - self.floor) as f64));
+            }
+            Token::Clear => self.stack.truncate(self.floor),
+            Token::Put => {
+                let value = self.pop("put")?;
+                writeln!(self.output, "{}", value)?;
+            }
+            Token::Print => {
+                let value = self.pop("print")?;
+                write!(self.output, "{}", value)?;
+            }
+            Token::Format => {
+                let text = self.format("format")?;
+                self.stack.push(StackType::String(text));
+            }
+            Token::Printf => {
+                let text = self.format("printf")?;
+                write!(self.output, "{}", text)?;
+            }
 
-                Token::Noteq => {
-                    // Pop items from stack
-                    let b = self.stack.pop() != self.stack.pop();
-                    let res = if b == true { 1.0 } else { 0.0 };
-                    self.stack.push(StackType::Float(res));
+            // Push the line and 1, or an empty string and 0 at end of input
+            Token::ReadLine => match self.read_line()? {
+                Some(line) => {
+                    self.stack.push(StackType::String(line));
+                    self.stack.push(StackType::Float(1.0));
+                }
+                None => {
+                    self.stack.push(StackType::String(String::new()));
+                    self.stack.push(StackType::Float(0.0));
                 }
+            },
 
-                Token::Bigger => {
-                    let b = self.stack.pop() < self.stack.pop();
-                    let res = if b == true { 1.0 } else { 0.0 };
-                    self.stack.push(StackType::Float(res));
+            // Push the number on the next line and 1, or 0 and 0 at end of input
+            Token::ReadNum => match self.read_line()? {
+                Some(line) => {
+                    let n = line.trim().parse::<f64>().map_err(|_| Error::NotANumber(line))?;
+                    self.stack.push(StackType::Float(n));
+                    self.stack.push(StackType::Float(1.0));
+                }
+                None => {
+                    self.stack.push(StackType::Float(0.0));
+                    self.stack.push(StackType::Float(0.0));
                 }
+            },
+
+            Token::ReadAll => {
+                self.output.flush()?;
+
+                let mut text = String::new();
+                self.input.read_to_string(&mut text)?;
+                self.stack.push(StackType::String(text));
+            }
+            Token::Eq => {
+                // Pop items from stack
+                let b = self.pop("eq")? == self.pop("eq")?;
+                let res = if b { 1.0 } else { 0.0 };
+                self.stack.push(StackType::Float(res));
+            }
+
+            Token::Noteq => {
+                // Pop items from stack
+                let b = self.pop("noteq")? != self.pop("noteq")?;
+                let res = if b { 1.0 } else { 0.0 };
+                self.stack.push(StackType::Float(res));
+            }
+
+            Token::Bigger => {
+                let b = self.pop("bigger")? < self.pop("bigger")?;
+                let res = if b { 1.0 } else { 0.0 };
+                self.stack.push(StackType::Float(res));
+            }
+
+            Token::Smaller => {
+                let b = self.pop("smaller")? > self.pop("smaller")?;
+                let res = if b { 1.0 } else { 0.0 };
+                self.stack.push(StackType::Float(res));
+            }
+
+            Token::Then => {
+                let body = self.next_body("then")?;
+                let stk = self.pop("then")?;
 
-                Token::Smaller => {
-                    let b = self.stack.pop() > self.stack.pop();
-                    let res = if b == true { 1.0 } else { 0.0 };
-                    self.stack.push(StackType::Float(res));
+                if stk == StackType::Float(1.0) {
+                    self.push_closure(body);
                 }
+            }
+
+            Token::Dup => {
+                // Duplicate top of stack
+                let item = self.pop("dup")?;
+
+                self.stack.push(item.clone());
+                self.stack.push(item);
+            }
+            Token::True => {
+                self.stack.push(StackType::Float(1.));
+            }
 
-                Token::Then => {
-                    let stk = self.stack.pop().unwrap();
+            Token::False => {
+                self.stack.push(StackType::Float(0.));
+            }
 
-                    if stk == StackType::Float(1.0) {
-                        // Run next code
-                        let scope = iter.next().unwrap();
+            Token::Drop => {
+                self.pop("drop")?;
+            }
 
-                        match scope {
-                            Token::Scope(tokens) => {
-                                self.parse(tokens.to_owned());
-                            }
+            Token::Str(content) => {
+                self.stack.push(StackType::String(content.to_owned()));
+            }
 
-                            _ => panic!("Expected scope after then"),
+            Token::Times => {
+                // Run code x times
+                let body = self.next_body("times")?;
+                let x = self.pop("times")?;
+                match x {
+                    StackType::Float(x) => {
+                        if x as u32 > 0 {
+                            self.push_frame(Frame {
+                                tokens: body.body.clone(),
+                                pc: 0,
+                                env: body.env.child(),
+                                kind: FrameKind::Times {
+                                    body,
+                                    remaining: x as u32 - 1,
+                                },
+                            });
                         }
-                    } else {
-                        iter.next();
                     }
+
+                    _ => return Err(Error::ExpectedType("times".to_string(), "number")),
                 }
+            }
 
-                Token::Dup => {
-                    // Duplicate top of stack
-                    let item = self.stack.pop().unwrap();
+            Token::Exec => {
+                let closure = self.pop_quote("exec")?;
+                self.push_closure(closure);
+            }
 
-                    self.stack.push(item.clone());
-                    self.stack.push(item);
-                }
-                Token::True => {
-                    self.stack.push(StackType::Float(1.));
-                }
+            Token::Len => {
+                let len = match self.pop("len")? {
+                    StackType::Array(items) => items.len(),
+                    StackType::String(string) => string.chars().count(),
+                    _ => return Err(Error::ExpectedType("len".to_string(), "array or string")),
+                };
+                self.stack.push(StackType::Float(len as f64));
+            }
 
-                Token::False => {
-                    self.stack.push(StackType::Float(0.));
-                }
+            // array index nth
+            Token::Nth => {
+                let index = match self.pop("nth")? {
+                    StackType::Float(f) => f,
+                    _ => return Err(Error::ExpectedType("nth".to_string(), "number")),
+                };
+                let items = self.pop_array("nth")?;
 
-                Token::Drop => {
-                    self.stack.pop();
+                match items.get(index as usize) {
+                    Some(item) if index >= 0.0 => self.stack.push(item.clone()),
+                    _ => return Err(Error::IndexOutOfRange(index, items.len())),
                 }
+            }
 
-                Token::Str(content) => {
-                    self.stack.push(StackType::String(content.to_owned()));
-                }
+            // array value push
+            Token::Push => {
+                let value = self.pop("push")?;
+                let mut items = self.pop_array("push")?;
 
-                Token::Times => {
-                    // Run code x times
-                    let x = self.stack.pop().unwrap();
-                    match x {
-                        StackType::Float(x) => {
-                            let next_token = iter.next().unwrap();
-
-                            for _i in 0..x as u32 {
-                                match next_token {
-                                    Token::Scope(tokens) => {
-                                        self.parse(tokens.to_vec());
-                                    }
-                                    _ => panic!("Expected scope after times"),
-                                }
-                            }
-                        }
+                items.push(value);
+                self.stack.push(StackType::Array(items));
+            }
+
+            Token::Import(tks) => {
+                self.parse(tks.clone())?;
+            }
+
+            Token::Object(props) => {
+                let mut names = vec![];
+
+                // Values stay on stack until the object is allocated so
+                // the collector can still see them. A value can't pop the
+                // ones before it or what was on stack before the object
+                let base = self.stack.len();
+                for (name, value) in props {
+                    let floor = std::mem::replace(&mut self.floor, base + names.len());
+                    let result = self.parse(value.clone());
+                    self.floor = floor;
+                    result?;
 
-                        _ => panic!("Cant iter over Not float type"),
+                    if self.stack.len() != base + names.len() + 1 {
+                        return Err(Error::PropertyValue(name.to_owned()));
                     }
+                    names.push(name.to_owned());
                 }
 
-                Token::Import(tks) => {
-                    self.parse(tks.to_vec());
+                let values = self.stack.split_off(base);
+                let ptr = self.alloc(HeapData::Object(ObjectData {
+                    properties: HashMap::from_iter(names.into_iter().zip(values)),
+                }));
+                self.stack.push(StackType::Pointer(ptr));
+            }
+
+            // Get the prop of object
+            Token::Get(prop_name) => {
+                let obj_ptr = self.stack.last().cloned();
+                let obj = self.pop_object("get")?;
+
+                match obj.properties.get(prop_name).cloned() {
+                    // Quotes are methods, they get the object pointer back as their receiver
+                    Some(StackType::Quote(closure)) => {
+                        self.stack.push(obj_ptr.unwrap());
+                        self.push_closure(closure);
+                    }
+                    Some(value) => self.stack.push(value),
+                    None => return Err(Error::MissingProperty(prop_name.to_owned())),
                 }
+            }
 
-                Token::Object(props) => {
-                    let props = props
-                        .to_owned()
-                        .into_iter();
+            // Write the top of stack into a prop of object
+            Token::SetProp(prop_name) => {
+                let value = self.pop("setp")?;
+                let obj = self.pop_object("setp")?;
 
-                    let object_properties =
-                        HashMap::from_iter(props);
+                obj.properties.insert(prop_name.to_owned(), value);
+            }
 
-                    self.heap.push(HeapData::Object(ObjectData { properties: object_properties }));
-                    self.stack.push(StackType::Pointer(self.heap.len() - 1));
-                }
+            Token::Has(prop_name) => {
+                let obj = self.pop_object("has")?;
 
-                // Get the prop of object
-                Token::Get(prop_name) => {
-                    let obj_ptr = self.stack.pop().unwrap();
+                let res = if obj.properties.contains_key(prop_name) { 1.0 } else { 0.0 };
+                self.stack.push(StackType::Float(res));
+            }
 
-                    let obj_ptr = match obj_ptr{
-                        StackType::Pointer(ptr) => ptr,
-                        _ => panic!("get keyword needs a pointer data on stack"),
-                    };
+            Token::Delete(prop_name) => {
+                let obj = self.pop_object("delete")?;
 
-                    match self.heap.get(obj_ptr).unwrap() {
-                        HeapData::Object(obj) => {
-                            self.parse(vec![obj.properties.get(prop_name).unwrap().clone()]);
-                        }
+                obj.properties.remove(prop_name);
+            }
+
+            Token::Keys => {
+                let obj = self.pop_object("keys")?;
+
+                let mut keys: Vec<&String> = obj.properties.keys().collect();
+                keys.sort();
+
+                let keys = keys.into_iter().map(|k| StackType::String(k.to_owned())).collect();
+                self.stack.push(StackType::Array(keys));
+            }
+
+            Token::MapNew => {
+                let ptr = self.alloc(HeapData::Map(BTreeMap::new()));
+                self.stack.push(StackType::Pointer(ptr));
+            }
+
+            // map key value mapset
+            Token::MapSet => {
+                let value = self.pop("mapset")?;
+                let key = self.pop_key("mapset")?;
+                let map = self.pop_map("mapset")?;
+
+                map.insert(key, value);
+            }
+
+            // map key mapget
+            Token::MapGet => {
+                let key = self.pop_key("mapget")?;
+                let map = self.pop_map("mapget")?;
+
+                let value = map.get(&key).cloned().ok_or(Error::MissingKey(key.to_string()))?;
+                self.stack.push(value);
+            }
 
-                        // _ => panic!("The pointer is not pointing to object")
-                    };
+            Token::MapHas => {
+                let key = self.pop_key("maphas")?;
+                let map = self.pop_map("maphas")?;
+
+                let res = if map.contains_key(&key) { 1.0 } else { 0.0 };
+                self.stack.push(StackType::Float(res));
+            }
+
+            Token::MapDel => {
+                let key = self.pop_key("mapdel")?;
+                let map = self.pop_map("mapdel")?;
+
+                map.remove(&key);
+            }
+
+            Token::MapKeys => {
+                let map = self.pop_map("mapkeys")?;
+
+                let keys = map.keys().map(|k| k.to_value()).collect();
+                self.stack.push(StackType::Array(keys));
+            }
+
+            Token::MapValues => {
+                let map = self.pop_map("mapvalues")?;
+
+                let values = map.values().cloned().collect();
+                self.stack.push(StackType::Array(values));
+            }
+
+            Token::MapLen => {
+                let map = self.pop_map("maplen")?;
+
+                let len = map.len() as f64;
+                self.stack.push(StackType::Float(len));
+            }
+
+            Token::Let(name) => {
+                let value = self.pop("let")?;
+
+                self.env.define(Let {
+                    name: name.to_string(),
+                    value,
+                })?
+            }
+
+            Token::Set(let_name) => {
+                let value = self.pop("set")?;
+
+                if !self.env.set(let_name, value) {
+                    return Err(self.undefined_let(let_name));
                 }
+            }
 
-                Token::Let(name) => match self.mem_scope {
-                    MemoryScope::Function => {
-                        let f = &mut self.functions[self.function_time];
-                        f.memory.push(Let {
-                            name: name.to_string(),
-                            value: self.stack.pop().unwrap(),
-                        })
-                    }
-                    MemoryScope::Global => self.memory.push(Let {
-                        name: name.to_string(),
-                        value: self.stack.pop().unwrap(),
-                    }),
-                },
+            Token::Mempop => {
+                let popped = self.globals.borrow_mut().lets.pop();
+                if let Some(x) = popped {
+                    self.stack.push(x.value);
+                }
+            }
 
-                Token::Set(let_name) => match self.mem_scope {
-                    MemoryScope::Function => {
-                        let function = &mut self.functions[self.function_time];
+            Token::Gc => {
+                // Collect now and push how many heap entries were freed
+                let freed = self.collect_garbage(&[]);
+                self.stack.push(StackType::Float(freed as f64));
+            }
 
-                        match function.memory.iter().position(|l| &l.name == let_name) {
-                            Some(l) => function.memory[l].value = self.stack.pop().unwrap(),
-                            None => panic!("Let is not defined!"),
-                        }
-                    }
-                    MemoryScope::Global => {
-                        match self.memory.iter().position(|l| &l.name == let_name) {
-                            Some(l) => self.memory[l].value = self.stack.pop().unwrap(),
-                            None => panic!("Let is not defined!"),
-                        }
+            Token::Heapusage => {
+                self.stack.push(StackType::Float(self.heap.len() as f64));
+            }
+
+            Token::Memusage => {
+                // return length of created variables
+                let len = self.globals.borrow().lets.len();
+                self.stack.push(StackType::Float(len as f64));
+            }
+
+            Token::Function(func) => {
+                let mut function = func.clone();
+                match self.next_scope() {
+                    Some(tokens) => {
+                        function.set_scope(tokens);
                     }
-                },
 
-                Token::Mempop => {
-                    match self.memory.pop() {
-                        Some(x) => self.stack.push(x.value),
-                        None => {}
-                    };
+                    None => panic!("Expected scope after function args"),
                 }
+                // Functions live in the scope they are defined in and see its
+                // lets, a later definition in the same scope replaces them
+                self.env.define_function(function);
+            }
 
-                Token::Memusage => {
-                    // return length of created variables
-                    self.stack.push(StackType::Float(self.memory.len() as f64));
-                }
+            Token::Call(name) => {
+                if let Some((f, env)) = self.env.function(name) {
+                    // Every call gets its own env nested in the one the function was
+                    // defined in, arguments are bound in declaration order
+                    let frame = env.child();
+                    for (arg, ty) in f.args.iter().zip(f.arg_types.iter()) {
+                        let value = self.pop(&format!("call {}", f.name))?;
 
-                Token::Function(func) => {
-                    let new_token = iter.next().unwrap();
-                    let mut function = func.clone();
-                    match new_token {
-                        Token::Scope(tokens) => {
-                            function.set_scope(tokens.to_vec());
+                        if !ty.accepts(Type::of(&value)) {
+                            return Err(Error::ArgType(f.name.clone(), arg.name.clone(), *ty, value));
                         }
+                        frame.define(Let {
+                            name: arg.name.clone(),
+                            value,
+                        })?;
+                    }
 
-                        _ => panic!("Expected scope after function args"),
+                    self.push_frame(Frame {
+                        tokens: f.scope.clone(),
+                        pc: 0,
+                        env: frame,
+                        kind: FrameKind::Call(f.name.clone(), f.results.clone()),
+                    });
+                } else {
+                    // A let holding a quote can be called like a function
+                    match self.env.get(name) {
+                        Some(StackType::Quote(closure)) => self.push_closure(closure),
+                        Some(_) => return Err(Error::ExpectedType("call".to_string(), "quote")),
+                        None => return Err(self.undefined_function(name)),
                     }
-                    self.functions.push(function);
                 }
+            }
 
-                Token::Call(name) => match self.functions.iter().position(|f| &f.name == name) {
-                    Some(ok) => {
-                        self.function_time = ok;
-                        let f = &mut self.functions[self.function_time];
-                        let mut args = f.memory.iter_mut();
-                        while let Some(arg) = args.next() {
-                            arg.value = self.stack.pop().unwrap();
-                        }
-                        self.mem_scope = MemoryScope::Function;
-                        self.parse(self.functions.get(ok).unwrap().to_owned().scope);
-                        self.mem_scope = MemoryScope::Global;
+            Token::Throw => return Err(Error::Thrown(self.pop("throw")?)),
+
+            Token::Try(body, handler) => {
+                let depth = self.stack.len();
+                let handler = Closure {
+                    body: handler.clone(),
+                    env: self.env.clone(),
+                };
+
+                self.push_frame(Frame {
+                    tokens: body.clone(),
+                    pc: 0,
+                    env: self.env.child(),
+                    kind: FrameKind::Try { handler, depth },
+                });
+            }
+
+            Token::ReadFile => {
+                let path = self.pop_path("readfile", FileAccess::Read)?;
+
+                let text = fs::read_to_string(&path).map_err(|e| file_error(&path, e))?;
+                self.stack.push(StackType::String(text));
+            }
+
+            // path text writefile
+            Token::WriteFile => {
+                let text = self.pop_string("writefile")?;
+                let path = self.pop_path("writefile", FileAccess::ReadWrite)?;
+
+                fs::write(&path, text).map_err(|e| file_error(&path, e))?;
+            }
+
+            // path text appendfile
+            Token::AppendFile => {
+                let text = self.pop_string("appendfile")?;
+                let path = self.pop_path("appendfile", FileAccess::ReadWrite)?;
+
+                fs::OpenOptions::new()
+                    .append(true)
+                    .create(true)
+                    .open(&path)
+                    .and_then(|mut file| file.write_all(text.as_bytes()))
+                    .map_err(|e| file_error(&path, e))?;
+            }
+
+            // Names of the entries of a directory, in order
+            Token::ListDir => {
+                let path = self.pop_path("listdir", FileAccess::Read)?;
+
+                let mut names = fs::read_dir(&path)
+                    .and_then(|entries| entries.collect::<io::Result<Vec<_>>>())
+                    .map_err(|e| file_error(&path, e))?
+                    .into_iter()
+                    .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                    .collect::<Vec<String>>();
+                names.sort();
+
+                self.stack.push(StackType::Array(names.into_iter().map(StackType::String).collect()));
+            }
+
+            Token::Exists => {
+                let path = self.pop_path("exists", FileAccess::Read)?;
+
+                let res = if std::path::Path::new(&path).exists() { 1.0 } else { 0.0 };
+                self.stack.push(StackType::Float(res));
+            }
+
+            Token::DeleteFile => {
+                let path = self.pop_path("deletefile", FileAccess::ReadWrite)?;
+
+                fs::remove_file(&path).map_err(|e| file_error(&path, e))?;
+            }
+
+            Token::Args => {
+                let args = self.args.iter().map(|a| StackType::String(a.to_owned())).collect();
+                self.stack.push(StackType::Array(args));
+            }
+
+            // Push the value and 1, or an empty string and 0 when it isn't set
+            Token::GetEnv => {
+                let name = self.pop_string("getenv")?;
+
+                match std::env::var(name) {
+                    Ok(value) => {
+                        self.stack.push(StackType::String(value));
+                        self.stack.push(StackType::Float(1.0));
                     }
-                    None => {}
-                },
+                    Err(_) => {
+                        self.stack.push(StackType::String(String::new()));
+                        self.stack.push(StackType::Float(0.0));
+                    }
+                }
+            }
 
-                Token::Array(tokens) => {
-                    let mut parser = Self::new();
-                    parser.parse(tokens.to_owned());
+            // name value setenv
+            Token::SetEnv => {
+                let value = self.pop_string("setenv")?;
+                let name = self.pop_string("setenv")?;
 
-                    self.stack.push(StackType::Array(parser.stack))
+                if name.is_empty() || name.contains(['=', '\0']) {
+                    return Err(Error::InvalidEnv(format!("{:?} is not a variable name", name)));
+                }
+                if value.contains('\0') {
+                    return Err(Error::InvalidEnv(format!("value of {} contains a NUL character", name)));
                 }
+                std::env::set_var(name, value);
+            }
 
-                Token::Scope(tokens) => {
-                    self.parse(tokens.to_owned());
+            Token::Exit => match self.pop("exit")? {
+                StackType::Float(code) if code.fract() == 0.0 && (0.0..=255.0).contains(&code) => {
+                    return Err(Error::Exit(code as i32))
                 }
+                StackType::Float(code) => return Err(Error::Domain("exit", code.to_string())),
+                _ => return Err(Error::ExpectedType("exit".to_string(), "number")),
+            },
 
-                Token::Ident(name) => match self.mem_scope {
-                    MemoryScope::Global => match self.memory.iter().position(|l| &l.name == name) {
-                        Some(ok) => {
-                            self.stack.push(self.memory[ok].value.clone());
-                        }
-                        None => {}
-                    },
+            Token::Assert => {
+                let value = self.pop("assert")?;
 
-                    MemoryScope::Function => {
-                        let args = &self.functions[self.function_time].memory;
-                        match args.iter().position(|l| &l.name == name) {
-                            Some(ok) => {
-                                self.stack.push(args[ok].value.clone());
-                            }
+                if value != StackType::Float(1.0) {
+                    return Err(Error::AssertionFailed(format!("`assert` got {:?}", value)));
+                }
+            }
 
-                            None => {}
-                        }
-                    }
+            // actual expected assert_eq
+            Token::AssertEq => {
+                let expected = self.pop("assert_eq")?;
+                let actual = self.pop("assert_eq")?;
+
+                if actual != expected {
+                    return Err(Error::AssertionFailed(format!(
+                        "{:?} is not equal to {:?}",
+                        actual, expected
+                    )));
+                }
+            }
+
+            // Compares the whole stack, bottom first, with an array
+            Token::AssertStack => {
+                let expected = self.pop_array("assert_stack")?;
+
+                if self.stack != expected {
+                    return Err(Error::AssertionFailed(format!(
+                        "stack is {:?} but should be {:?}",
+                        self.stack, expected
+                    )));
+                }
+            }
+
+            Token::Test(name, _, body) => self.tests.push((
+                name.to_owned(),
+                Closure {
+                    body: body.clone(),
+                    env: self.env.clone(),
                 },
+            )),
+
+            Token::Array(tokens) => {
+                // Evaluate in the current context, then collect whatever
+                // the body left above the stack depth we started at. The
+                // body can't pop the values that were already there
+                let base = self.stack.len();
+                let floor = std::mem::replace(&mut self.floor, base);
+                let result = self.parse(tokens.clone());
+                self.floor = floor;
+                result?;
+
+                let items = self.stack.split_off(base);
+                self.stack.push(StackType::Array(items))
             }
+
+            // A scope on its own is pushed as a value, run it with exec
+            Token::Scope(tokens) => {
+                self.stack.push(StackType::Quote(Closure {
+                    body: tokens.clone(),
+                    env: self.env.clone(),
+                }));
+            }
+
+            Token::Ident(name) => match self.env.get(name) {
+                Some(value) => self.stack.push(value),
+                None => return Err(self.undefined_let(name)),
+            },
         }
+
+        Ok(())
     }
 }
@@ -0,0 +1,334 @@
+use crate::{
+    error::Error,
+    heap::{Heap, HeapData, ObjectData},
+    interpreter::StackType,
+};
+use std::collections::HashMap;
+
+/// Arrays and objects nested deeper than this are rejected instead of
+/// overflowing the stack of the parser
+const MAX_DEPTH: usize = 256;
+
+/// A parsed JSON value
+#[derive(Debug)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Moves the value to the stack, objects are allocated on `heap`
+    ///
+    /// Jsl has no booleans or null: `true` is 1, `false` and `null` are 0
+    pub fn into_value(self, heap: &mut Heap) -> StackType {
+        match self {
+            Self::Null | Self::Bool(false) => StackType::Float(0.0),
+            Self::Bool(true) => StackType::Float(1.0),
+            Self::Number(n) => StackType::Float(n),
+            Self::String(s) => StackType::String(s),
+            Self::Array(items) => StackType::Array(items.into_iter().map(|j| j.into_value(heap)).collect()),
+            Self::Object(members) => {
+                let properties: HashMap<String, StackType> =
+                    members.into_iter().map(|(k, v)| (k, v.into_value(heap))).collect();
+                StackType::Pointer(heap.alloc(HeapData::Object(ObjectData { properties })))
+            }
+        }
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, reason: &str) -> Error {
+        Error::Json(format!("{} at byte {}", reason, self.pos))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t' | '\n' | '\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), Error> {
+        match self.next() {
+            Some(got) if got == c => Ok(()),
+            Some(got) => Err(self.error(&format!("expected `{}` but found `{}`", c, got))),
+            None => Err(self.error(&format!("expected `{}` but the text ended", c))),
+        }
+    }
+
+    fn literal(&mut self, word: &str, json: Json) -> Result<Json, Error> {
+        if self.text[self.pos..].starts_with(word) {
+            self.pos += word.len();
+            Ok(json)
+        } else {
+            Err(self.error("unknown value"))
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, Error> {
+        self.skip_whitespace();
+
+        match self.peek() {
+            Some('{') => self.nested(Self::object),
+            Some('[') => self.nested(Self::array),
+            Some('"') => Ok(Json::String(self.string()?)),
+            Some('t') => self.literal("true", Json::Bool(true)),
+            Some('f') => self.literal("false", Json::Bool(false)),
+            Some('n') => self.literal("null", Json::Null),
+            Some('-' | '0'..='9') => self.number(),
+            Some(c) => Err(self.error(&format!("unexpected `{}`", c))),
+            None => Err(self.error("expected a value but the text ended")),
+        }
+    }
+
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<Json, Error>) -> Result<Json, Error> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(self.error("nested too deep"));
+        }
+
+        let json = parse(self);
+        self.depth -= 1;
+        json
+    }
+
+    fn array(&mut self) -> Result<Json, Error> {
+        self.expect('[')?;
+        let mut items = vec![];
+
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.pos += 1;
+            return Ok(Json::Array(items));
+        }
+
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => {}
+                Some(']') => return Ok(Json::Array(items)),
+                _ => return Err(self.error("expected `,` or `]` in array")),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, Error> {
+        self.expect('{')?;
+        let mut members = vec![];
+
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.pos += 1;
+            return Ok(Json::Object(members));
+        }
+
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            members.push((key, self.value()?));
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => {}
+                Some('}') => return Ok(Json::Object(members)),
+                _ => return Err(self.error("expected `,` or `}` in object")),
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, Error> {
+        let digits = self.text.get(self.pos..self.pos + 4).ok_or_else(|| self.error("short \\u escape"))?;
+        let code = u32::from_str_radix(digits, 16).map_err(|_| self.error("invalid \\u escape"))?;
+        self.pos += 4;
+        Ok(code)
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        self.expect('"')?;
+        let mut s = String::new();
+
+        loop {
+            match self.next() {
+                Some('"') => return Ok(s),
+                Some('\\') => match self.next() {
+                    Some('"') => s.push('"'),
+                    Some('\\') => s.push('\\'),
+                    Some('/') => s.push('/'),
+                    Some('b') => s.push('\u{8}'),
+                    Some('f') => s.push('\u{c}'),
+                    Some('n') => s.push('\n'),
+                    Some('r') => s.push('\r'),
+                    Some('t') => s.push('\t'),
+                    Some('u') => {
+                        let mut code = self.hex4()?;
+                        // Characters past the first plane are written as a
+                        // pair of surrogates
+                        if (0xd800..0xdc00).contains(&code) && self.text[self.pos..].starts_with("\\u") {
+                            self.pos += 2;
+                            let low = self.hex4()?;
+                            if !(0xdc00..0xe000).contains(&low) {
+                                return Err(self.error("invalid surrogate pair"));
+                            }
+                            code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                        }
+                        s.push(char::from_u32(code).ok_or_else(|| self.error("invalid \\u escape"))?);
+                    }
+                    _ => return Err(self.error("invalid escape")),
+                },
+                Some(c) if (c as u32) < 0x20 => return Err(self.error("control character in string")),
+                Some(c) => s.push(c),
+                None => return Err(self.error("string is not closed")),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, Error> {
+        let start = self.pos;
+        let digits = |p: &mut Self| {
+            let from = p.pos;
+            while p.peek().is_some_and(|c| c.is_ascii_digit()) {
+                p.pos += 1;
+            }
+            p.pos > from
+        };
+
+        if self.peek() == Some('-') {
+            self.pos += 1;
+        }
+        if self.peek() == Some('0') {
+            self.pos += 1;
+        } else if !digits(self) {
+            return Err(self.error("expected digits"));
+        }
+        if self.peek() == Some('.') {
+            self.pos += 1;
+            if !digits(self) {
+                return Err(self.error("expected digits after `.`"));
+            }
+        }
+        if matches!(self.peek(), Some('e' | 'E')) {
+            self.pos += 1;
+            if matches!(self.peek(), Some('+' | '-')) {
+                self.pos += 1;
+            }
+            if !digits(self) {
+                return Err(self.error("expected digits in exponent"));
+            }
+        }
+
+        self.text[start..self.pos]
+            .parse()
+            .map(Json::Number)
+            .map_err(|_| self.error("invalid number"))
+    }
+}
+
+/// Parses a whole JSON text
+pub fn parse(text: &str) -> Result<Json, Error> {
+    let mut parser = Parser { text, pos: 0, depth: 0 };
+    let json = parser.value()?;
+
+    parser.skip_whitespace();
+    if parser.pos < text.len() {
+        return Err(parser.error("unexpected text after the value"));
+    }
+
+    Ok(json)
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Writes `value` as JSON, following pointers into `heap`. `path` holds the
+/// pointers being written, to find objects that contain themselves
+fn write(out: &mut String, value: &StackType, heap: &Heap, path: &mut Vec<usize>) -> Result<(), Error> {
+    match value {
+        StackType::Float(n) if n.is_finite() => out.push_str(&n.to_string()),
+        StackType::Float(n) => return Err(Error::Json(format!("{} can't be written as JSON", n))),
+        StackType::String(s) => write_string(out, s),
+        StackType::Quote(_) => return Err(Error::Json("a quote can't be written as JSON".to_string())),
+        StackType::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write(out, item, heap, path)?;
+            }
+            out.push(']');
+        }
+        StackType::Pointer(p) => {
+            if path.contains(p) {
+                return Err(Error::Json(format!("0x{:x} contains itself", p)));
+            }
+            path.push(*p);
+
+            // Properties are written in order of name so the text is the
+            // same on every run
+            let members: Vec<(String, &StackType)> = match heap.get(*p)? {
+                HeapData::Object(obj) => {
+                    let mut members: Vec<_> = obj.properties.iter().map(|(k, v)| (k.clone(), v)).collect();
+                    members.sort_by(|a, b| a.0.cmp(&b.0));
+                    members
+                }
+                HeapData::Map(map) => map.iter().map(|(k, v)| (k.to_string(), v)).collect(),
+            };
+
+            out.push('{');
+            for (i, (key, value)) in members.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_string(out, &key);
+                out.push(':');
+                write(out, value, heap, path)?;
+            }
+            out.push('}');
+
+            path.pop();
+        }
+    }
+
+    Ok(())
+}
+
+/// `value` as JSON text
+pub fn stringify(value: &StackType, heap: &Heap) -> Result<String, Error> {
+    let mut out = String::new();
+    write(&mut out, value, heap, &mut vec![])?;
+    Ok(out)
+}
@@ -1,20 +1,122 @@
 use crate::{interpreter::StackType, token::Token, types::*};
 use std::fs::File;
 use std::io::prelude::*;
+use std::rc::Rc;
 
 fn is_string_numeric(str: String) -> bool {
-    let mut result = false;
-    for c in str.chars() {
-        if !c.is_numeric() {
-            result = false;
+    str.parse::<f64>().is_ok()
+}
+
+/// Splits source into words at whitespace, with the line every word starts
+/// on. A word starting with `"` goes on to the closing `"`, so a quoted
+/// string is one word even when it has whitespace in it
+fn words(source: &str) -> Vec<(&str, usize)> {
+    let mut words = vec![];
+    let mut line = 1;
+    let mut chars = source.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        if c.is_whitespace() {
+            if c == '\n' {
+                line += 1;
+            }
+            continue;
+        }
+
+        let word_line = line;
+        let string = c == '"';
+        let mut quoted = string;
+        let mut escaped = false;
+        let mut end = source.len();
+
+        while let Some(&(i, c)) = chars.peek() {
+            if quoted {
+                match c {
+                    '\n' => line += 1,
+                    '\\' if !escaped => {
+                        escaped = true;
+                        chars.next();
+                        continue;
+                    }
+                    '"' if !escaped => quoted = false,
+                    _ => {}
+                }
+                escaped = false;
+            } else if c.is_whitespace() {
+                end = i;
+                break;
+            } else if string {
+                panic!("String on line {} must be followed by whitespace, found {}", word_line, c);
+            }
+            chars.next();
         }
+
+        if quoted {
+            panic!("String starting on line {} is not closed with \"", word_line);
+        }
+        words.push((&source[start..end], word_line));
     }
 
-    if str.parse::<f64>().is_ok() {
-        result = true;
+    words
+}
+
+/// Text of a quoted word without the quotes, with `\n`, `\t`, `\"` and `\\`
+/// replaced by what they stand for
+fn unquote(word: &str) -> String {
+    let mut text = String::new();
+    let mut chars = word[1..word.len() - 1].chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => text.push('\n'),
+            Some('t') => text.push('\t'),
+            Some(c) => text.push(c),
+            None => text.push('\\'),
+        }
     }
 
-    return result;
+    text
+}
+
+/// Name of a let or an argument, only a word the lexer reads as a name can
+/// be one. A keyword, number or string would never be read back
+fn name<'a>(word: &'a str, line: usize, what: &str) -> &'a str {
+    if !matches!(Lexer::word(word), Word::Value) {
+        panic!("{} on line {} is a keyword and can't be the name of {}", word, line, what);
+    }
+    if !matches!(Lexer::value(word), Token::Ident(_)) {
+        panic!("{} on line {} is a value and can't be the name of {}", word, line, what);
+    }
+    word
+}
+
+/// What a word is to the lexer
+enum Word {
+    /// Keyword that is a token by itself
+    Token(Token),
+    /// Keywords that read the words after them
+    Str,
+    Import,
+    Let,
+    Set,
+    Fn,
+    Call,
+    Try,
+    Test,
+    Array,
+    Scope,
+    Object,
+    Get,
+    SetProp,
+    Has,
+    Delete,
+    /// A string, a number or a name
+    Value,
 }
 
 pub struct Lexer {
@@ -28,47 +130,16 @@ impl Lexer {
 
     pub fn lex(&self) -> Vec<Token> {
         let mut result: Vec<Token> = vec![];
-        let aschar: Vec<&str> = self.source.split_whitespace().collect();
+        let split = words(&self.source);
+        let aschar: Vec<&str> = split.iter().map(|(word, _)| *word).collect();
         let mut iter = aschar.iter();
         let mut index = 0;
-        let mut open_scope = 0;
 
         while let Some(word) = iter.next() {
-            match word {
-                //&"0" | &"1" | &"2" | &"3" | &"4" | &"5" | &"6" | &"7" | &"8" | &"9" => {}
-                &"add" => result.push(Token::Add),
-                &"minus" => result.push(Token::Minus),
-                &"div" => result.push(Token::Div),
-                &"mul" => result.push(Token::Mul),
-                &"swap" => result.push(Token::Swap),
-                &"rot" => result.push(Token::Rot),
-                &"mod" => result.push(Token::Mod),
-                &"put" => result.push(Token::Put),
-
-                &"eq" => {
-                    result.push(Token::Eq);
-                }
-
-                &"noteq" => {
-                    // Pop items from stack
-                    result.push(Token::Noteq)
-                }
-
-                &"bigger" => result.push(Token::Bigger),
-
-                &"smaller" => result.push(Token::Smaller),
-
-                &"then" => result.push(Token::Then),
+            match Self::word(word) {
+                Word::Token(token) => result.push(token),
 
-                &"dup" => result.push(Token::Dup),
-
-                &"true" => result.push(Token::True),
-
-                &"false" => result.push(Token::False),
-
-                &"drop" => result.push(Token::Drop),
-
-                &"str" => {
+                Word::Str => {
                     // Next element in word will be a string
                     let content = aschar[index + 1];
 
@@ -76,9 +147,7 @@ impl Lexer {
                     result.push(Token::Str(content.to_string()))
                 }
 
-                &"times" => result.push(Token::Times),
-
-                &"import" => {
+                Word::Import => {
                     let file_name = aschar[index + 1];
 
                     Self::next(&mut iter, &mut index);
@@ -86,7 +155,7 @@ impl Lexer {
                     // Check filename for std
                     let file_data = match file_name {
                         //"math" => include_str!("../std/math.jsl").to_string(),
-                        //"std" => include_str!("../std/std.jsl").to_string(),
+                        "std" => include_str!("../std/std.jsl").to_string(),
                         //"memory" => include_str!("../std/memory.jsl").to_string(),
                         _ => {
                             // read file
@@ -98,124 +167,394 @@ impl Lexer {
                         }
                     };
 
-                    result.push(Token::Import(Self::new(file_data).lex()))
+                    result.push(Token::Import(Self::new(file_data).lex().into()))
                 }
 
-                &"let" => {
-                    let let_name = aschar[index + 1];
+                Word::Let => {
+                    let let_name = name(aschar[index + 1], split[index].1, "a let");
                     Self::next(&mut iter, &mut index);
                     result.push(Token::Let(let_name.to_string()))
                 }
 
-                &"set" => {
-                    let let_name = aschar[index + 1];
+                Word::Set => {
+                    let let_name = name(aschar[index + 1], split[index].1, "a let");
                     Self::next(&mut iter, &mut index);
                     result.push(Token::Set(let_name.to_string()))
                 }
 
-                &"fn" => {
+                Word::Fn => {
                     // first find function name
                     let fn_name = aschar[index + 1];
                     Self::next(&mut iter, &mut index);
 
                     let mut fn_args: Vec<Let> = vec![];
+                    let mut arg_types: Vec<Type> = vec![];
+                    let mut effect = None;
 
                     Self::next(&mut iter, &mut index);
                     while aschar[index] != "->" {
+                        // Declared stack effect
+                        if aschar[index] == "(" {
+                            effect = Some(Self::stack_effect(&aschar, &mut iter, &mut index));
+                            Self::next(&mut iter, &mut index);
+                            continue;
+                        }
+
+                        // Arg with optional type, `name:type`
+                        let (arg_name, arg_type) = match aschar[index].split_once(':') {
+                            Some((name, ty)) => (name, Self::type_annotation(ty)),
+                            None => (aschar[index], Type::Any),
+                        };
+                        let arg_name = name(arg_name, split[index].1, "an argument");
+
                         fn_args.push(Let {
-                            name: aschar[index].to_string(),
+                            name: arg_name.to_string(),
                             value: StackType::Float(0.0),
                         });
+                        arg_types.push(arg_type);
+                        Self::next(&mut iter, &mut index);
+                    }
+
+                    // Result types between -> and the scope
+                    let mut results: Vec<Type> = vec![];
+                    while aschar.get(index + 1).is_some_and(|w| *w != "{") {
+                        results.push(Self::type_annotation(aschar[index + 1]));
                         Self::next(&mut iter, &mut index);
                     }
 
-                    result.push(Token::Function(Function::new(fn_name.to_string(), fn_args)));
+                    let mut function = Function::new(fn_name.to_string(), fn_args);
+                    function.arg_types = arg_types;
+                    function.effect = effect;
+                    if !results.is_empty() {
+                        function.results = Some(results);
+                    }
+                    result.push(Token::Function(function));
                 }
 
-                &"call" => {
+                Word::Call => {
                     let name = aschar[index + 1];
                     Self::next(&mut iter, &mut index);
                     result.push(Token::Call(name.to_string()));
                 }
 
-                &"mempop" => result.push(Token::Mempop),
-                &"memusage" => result.push(Token::Memusage),
+                Word::Try => {
+                    Self::next(&mut iter, &mut index);
+                    if aschar.get(index) != Some(&"{") {
+                        panic!("Expected {{ after try");
+                    }
+                    let body = Self::block(&aschar, &mut iter, &mut index, "{", "}");
 
-                // Array
-                &"[" => {
-                    let mut array_body = String::new();
                     Self::next(&mut iter, &mut index);
+                    if aschar.get(index) != Some(&"catch") {
+                        panic!("Expected catch after try block");
+                    }
 
-                    while aschar[index] != "]" {
-                        array_body.push_str(&(aschar[index].to_owned() + " "));
-                        Self::next(&mut iter, &mut index);
+                    Self::next(&mut iter, &mut index);
+                    if aschar.get(index) != Some(&"{") {
+                        panic!("Expected {{ after catch");
                     }
+                    let handler = Self::block(&aschar, &mut iter, &mut index, "{", "}");
 
-                    result.push(Token::Array(Self::new(array_body).lex()));
+                    result.push(Token::Try(Self::new(body).lex().into(), Self::new(handler).lex().into()));
                 }
 
-                // Scope
-                &"{" => {
-                    open_scope += 1;
-                    let mut scope_body = String::new();
+                Word::Test => {
+                    let line = split[index].1;
+                    let test_name = aschar[index + 1];
                     Self::next(&mut iter, &mut index);
 
-                    while aschar[index] != "}" || open_scope != 1 {
-                        if aschar[index] == "}" {
-                            open_scope -= 1;
-                        } else if aschar[index] == "{" {
-                            open_scope += 1;
+                    // The runner finds tests by name
+                    for token in result.iter() {
+                        if let Token::Test(name, first, _) = token {
+                            if name == test_name {
+                                panic!("Test {} on line {} is already declared on line {}", test_name, line, first);
+                            }
                         }
+                    }
 
-                        scope_body.push_str(&(aschar[index].to_owned() + " "));
-                        Self::next(&mut iter, &mut index);
+                    Self::next(&mut iter, &mut index);
+                    if aschar.get(index) != Some(&"{") {
+                        panic!("Expected {{ after test {}", test_name);
                     }
+                    let body = Self::block(&aschar, &mut iter, &mut index, "{", "}");
 
-                    result.push(Token::Scope(Self::new(scope_body).lex()));
+                    result.push(Token::Test(test_name.to_string(), line, Self::new(body).lex().into()));
                 }
 
-                &"object" => {
-                    Self::next(&mut iter, &mut index);
-                    Self::next(&mut iter, &mut index);
+                Word::Array => {
+                    let array_body = Self::block(&aschar, &mut iter, &mut index, "[", "]");
 
-                    let mut properties: Vec<(String, Token)>= Vec::new();
+                    result.push(Token::Array(Self::new(array_body).lex().into()));
+                }
 
-                    while aschar[index] != "}" {
-                        if aschar[index + 1] != "=" {
-                            panic!("You must define property with equal (=)");
-                        }
-                        let property_name = aschar[index];
-                        let property_value = aschar[index + 2]; // 1 is ':' char
+                Word::Scope => {
+                    let scope_body = Self::block(&aschar, &mut iter, &mut index, "{", "}");
 
-                        properties.push(
-                            (property_name.to_owned(), Self::new(property_value.to_owned()).lex().get(0).unwrap().clone())
-                        );
+                    result.push(Token::Scope(Self::new(scope_body).lex().into()));
+                }
 
-                        Self::next(&mut iter, &mut index);
-                        Self::next(&mut iter, &mut index);
-                        Self::next(&mut iter, &mut index);
+                Word::Object => {
+                    Self::next(&mut iter, &mut index);
+                    if aschar[index] != "{" {
+                        panic!("Expected {{ after object");
                     }
 
-                    result.push(Token::Object(properties));
+                    let object_body = Self::block(&aschar, &mut iter, &mut index, "{", "}");
+                    let words: Vec<&str> = words(&object_body).into_iter().map(|(word, _)| word).collect();
+
+                    result.push(Token::Object(Self::properties(&words)));
                 }
 
-                &"get" => {
+                Word::Get => {
                     let property_name = aschar[index + 1];
                     Self::next(&mut iter, &mut index);
                     result.push(Token::Get(property_name.to_string()));
                 }
 
-                _ => {
-                    if is_string_numeric(word.to_string()) {
-                        result.push(Token::Number(word.parse::<f64>().unwrap()));
-                    } else {
-                        result.push(Token::Ident(word.to_string()))
-                    }
+                Word::SetProp => {
+                    let property_name = aschar[index + 1];
+                    Self::next(&mut iter, &mut index);
+                    result.push(Token::SetProp(property_name.to_string()));
+                }
+
+                Word::Has => {
+                    let property_name = aschar[index + 1];
+                    Self::next(&mut iter, &mut index);
+                    result.push(Token::Has(property_name.to_string()));
                 }
+
+                Word::Delete => {
+                    let property_name = aschar[index + 1];
+                    Self::next(&mut iter, &mut index);
+                    result.push(Token::Delete(property_name.to_string()));
+                }
+
+                Word::Value => result.push(Self::value(word)),
             }
             index += 1;
         }
-        return result;
+        result
+    }
+
+    /// What the lexer makes of a word, every keyword is spelled here
+    fn word(word: &str) -> Word {
+        match word {
+            "add" => Word::Token(Token::Add),
+            "minus" => Word::Token(Token::Minus),
+            "div" => Word::Token(Token::Div),
+            "mul" => Word::Token(Token::Mul),
+            "swap" => Word::Token(Token::Swap),
+            "rot" => Word::Token(Token::Rot),
+            "over" => Word::Token(Token::Over),
+            "nip" => Word::Token(Token::Nip),
+            "tuck" => Word::Token(Token::Tuck),
+            "-rot" => Word::Token(Token::UnRot),
+            "2dup" => Word::Token(Token::TwoDup),
+            "2drop" => Word::Token(Token::TwoDrop),
+            "2swap" => Word::Token(Token::TwoSwap),
+            "pick" => Word::Token(Token::Pick),
+            "roll" => Word::Token(Token::Roll),
+            "depth" => Word::Token(Token::Depth),
+            "clear" => Word::Token(Token::Clear),
+            "mod" => Word::Token(Token::Mod),
+            "sqrt" => Word::Token(Token::Sqrt),
+            "pow" => Word::Token(Token::Pow),
+            "abs" => Word::Token(Token::Abs),
+            "floor" => Word::Token(Token::Floor),
+            "ceil" => Word::Token(Token::Ceil),
+            "round" => Word::Token(Token::Round),
+            "min" => Word::Token(Token::Min),
+            "max" => Word::Token(Token::Max),
+            "sin" => Word::Token(Token::Sin),
+            "cos" => Word::Token(Token::Cos),
+            "tan" => Word::Token(Token::Tan),
+            "log" => Word::Token(Token::Log),
+            "exp" => Word::Token(Token::Exp),
+            "pi" => Word::Token(Token::Pi),
+            "e" => Word::Token(Token::E),
+            "gcd" => Word::Token(Token::Gcd),
+            "rand" => Word::Token(Token::Rand),
+            "randint" => Word::Token(Token::RandInt),
+            "shuffle" => Word::Token(Token::Shuffle),
+            "seed" => Word::Token(Token::Seed),
+            "now_ms" => Word::Token(Token::NowMs),
+            "unixtime" => Word::Token(Token::UnixTime),
+            "sleep" => Word::Token(Token::Sleep),
+            "isotime" => Word::Token(Token::IsoTime),
+            "json_parse" => Word::Token(Token::JsonParse),
+            "json_stringify" => Word::Token(Token::JsonStringify),
+            "put" => Word::Token(Token::Put),
+            "print" => Word::Token(Token::Print),
+            "format" => Word::Token(Token::Format),
+            "printf" => Word::Token(Token::Printf),
+            "readline" => Word::Token(Token::ReadLine),
+            "readnum" => Word::Token(Token::ReadNum),
+            "readall" => Word::Token(Token::ReadAll),
+            "readfile" => Word::Token(Token::ReadFile),
+            "writefile" => Word::Token(Token::WriteFile),
+            "appendfile" => Word::Token(Token::AppendFile),
+            "listdir" => Word::Token(Token::ListDir),
+            "exists" => Word::Token(Token::Exists),
+            "deletefile" => Word::Token(Token::DeleteFile),
+            "args" => Word::Token(Token::Args),
+            "getenv" => Word::Token(Token::GetEnv),
+            "setenv" => Word::Token(Token::SetEnv),
+            "exit" => Word::Token(Token::Exit),
+            "eq" => Word::Token(Token::Eq),
+            "noteq" => Word::Token(Token::Noteq),
+            "bigger" => Word::Token(Token::Bigger),
+            "smaller" => Word::Token(Token::Smaller),
+            "then" => Word::Token(Token::Then),
+            "dup" => Word::Token(Token::Dup),
+            "true" => Word::Token(Token::True),
+            "false" => Word::Token(Token::False),
+            "drop" => Word::Token(Token::Drop),
+            "str" => Word::Str,
+            "times" => Word::Token(Token::Times),
+            "exec" => Word::Token(Token::Exec),
+            "len" => Word::Token(Token::Len),
+            "nth" => Word::Token(Token::Nth),
+            "push" => Word::Token(Token::Push),
+            "import" => Word::Import,
+            "let" => Word::Let,
+            "set" => Word::Set,
+            "fn" => Word::Fn,
+            "call" => Word::Call,
+            "throw" => Word::Token(Token::Throw),
+            "try" => Word::Try,
+            "assert" => Word::Token(Token::Assert),
+            "assert_eq" => Word::Token(Token::AssertEq),
+            "assert_stack" => Word::Token(Token::AssertStack),
+            "test" => Word::Test,
+            "mempop" => Word::Token(Token::Mempop),
+            "memusage" => Word::Token(Token::Memusage),
+            "gc" => Word::Token(Token::Gc),
+            "heapusage" => Word::Token(Token::Heapusage),
+            "[" => Word::Array,
+            "{" => Word::Scope,
+            "object" => Word::Object,
+            "get" => Word::Get,
+            "setp" => Word::SetProp,
+            "has" => Word::Has,
+            "delete" => Word::Delete,
+            "keys" => Word::Token(Token::Keys),
+            "mapnew" => Word::Token(Token::MapNew),
+            "mapset" => Word::Token(Token::MapSet),
+            "mapget" => Word::Token(Token::MapGet),
+            "maphas" => Word::Token(Token::MapHas),
+            "mapdel" => Word::Token(Token::MapDel),
+            "mapkeys" => Word::Token(Token::MapKeys),
+            "mapvalues" => Word::Token(Token::MapValues),
+            "maplen" => Word::Token(Token::MapLen),
+            _ => Word::Value,
+        }
+    }
+
+    /// Token of a word that isn't a keyword
+    fn value(word: &str) -> Token {
+        // Quoted string
+        if word.starts_with('"') {
+            Token::Str(unquote(word))
+        } else if is_string_numeric(word.to_string()) {
+            Token::Number(word.parse::<f64>().unwrap())
+        } else {
+            Token::Ident(word.to_string())
+        }
+    }
+
+    fn type_annotation(name: &str) -> Type {
+        match Type::parse(name) {
+            Some(ty) => ty,
+            None => panic!("Unknown type {}, expected num, str, arr, ptr, quote or any", name),
+        }
+    }
+
+    /// Reads a stack effect like `( a b -- c )`, leaves the index on `)`
+    fn stack_effect<T>(aschar: &[&str], iter: &mut T, index: &mut usize) -> StackEffect
+    where
+        T: Iterator,
+    {
+        let mut effect = StackEffect {
+            inputs: vec![],
+            outputs: vec![],
+        };
+        let mut after_dashes = false;
+        Self::next(iter, index);
+
+        while aschar[*index] != ")" {
+            match aschar[*index] {
+                "--" => after_dashes = true,
+                name if after_dashes => effect.outputs.push(name.to_string()),
+                name => effect.inputs.push(name.to_string()),
+            }
+            Self::next(iter, index);
+        }
+
+        if !after_dashes {
+            panic!("Stack effect of function needs --");
+        }
+
+        effect
+    }
+
+    /// Splits the body of an object literal into `name = value` pairs, the
+    /// value is every word up to the next `name =` that isn't nested inside
+    /// a block, array or another object
+    fn properties(words: &[&str]) -> Vec<(String, Rc<[Token]>)> {
+        let mut starts: Vec<usize> = vec![];
+        let mut depth = 0;
+
+        for (i, word) in words.iter().enumerate() {
+            if depth == 0 && words.get(i + 1) == Some(&"=") {
+                starts.push(i);
+            }
+
+            match *word {
+                "{" | "[" => depth += 1,
+                "}" | "]" => depth -= 1,
+                _ => {}
+            }
+        }
+
+        if !words.is_empty() && starts.first() != Some(&0) {
+            panic!("You must define property with equal (=)");
+        }
+
+        let mut properties: Vec<(String, Rc<[Token]>)> = Vec::new();
+
+        for (n, start) in starts.iter().enumerate() {
+            let end = starts.get(n + 1).copied().unwrap_or(words.len());
+            let property_value = words[start + 2..end].join(" ");
+
+            properties.push((words[*start].to_owned(), Self::new(property_value).lex().into()));
+        }
+
+        properties
+    }
+
+    /// Collects the words between `open` (at the current index) and its
+    /// matching `close`, so nested blocks of the same kind stay intact.
+    /// Leaves the index on the closing word.
+    fn block<T>(aschar: &[&str], iter: &mut T, index: &mut usize, open: &str, close: &str) -> String
+    where
+        T: Iterator,
+    {
+        let mut depth = 1;
+        let mut body = String::new();
+        Self::next(iter, index);
+
+        while aschar[*index] != close || depth != 1 {
+            if aschar[*index] == close {
+                depth -= 1;
+            } else if aschar[*index] == open {
+                depth += 1;
+            }
+
+            body.push_str(&(aschar[*index].to_owned() + " "));
+            Self::next(iter, index);
+        }
+
+        body
     }
 
     fn next<T>(iter: &mut T, index: &mut usize)
@@ -1,29 +1,161 @@
-use interpreter::Interpreter;
+use checker::Checker;
+use error::Error;
+use interpreter::{FileAccess, Interpreter};
 use lexer::Lexer;
-use std::env;
+use random::Rng;
 use std::fs::File;
 use std::io;
 use std::io::prelude::*;
 
+mod checker;
+mod env;
+mod error;
+mod format;
+mod heap;
 mod interpreter;
+mod json;
 mod lexer;
+mod output;
+mod random;
+mod runner;
+mod suggest;
+mod time;
 mod token;
 mod types;
 
-fn main() -> io::Result<()> {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 1 {
-        panic!("Args is not valid");
-    }
-    let mut file = File::open(&args[1])?;
+fn read_source(path: &str) -> io::Result<String> {
+    let mut file = File::open(path)?;
     let mut contents = String::new();
     file.read_to_string(&mut contents)?;
 
-    let lexer = Lexer::new(contents);
+    Ok(contents)
+}
+
+/// Command line options, written before the source file
+struct Options {
+    /// Print the stack left at the end of the program to stderr, bottom first
+    stack: bool,
+    /// Access the file keywords have, `--read-only` or `--sandbox` for none
+    files: FileAccess,
+    /// Seed of the random numbers, from the clock when not given. Tests run
+    /// with seed 0 unless one is given
+    seed: Option<u64>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            stack: false,
+            files: FileAccess::ReadWrite,
+            seed: None,
+        }
+    }
+}
+
+/// Runs the program at `path`, `args` are the arguments after it
+fn run(path: &str, args: Vec<String>, options: &Options) -> io::Result<()> {
+    let lexer = Lexer::new(read_source(path)?);
     let tokens = lexer.lex();
 
     let mut i = Interpreter::new();
-    i.parse(tokens);
+    i.files = options.files;
+    i.args = args;
+    if let Some(seed) = options.seed {
+        i.rng = Rng::new(seed);
+    }
+    let result = i.parse(tokens);
+    i.output.flush()?;
+
+    match result {
+        Ok(()) => {}
+        Err(Error::Exit(code)) => std::process::exit(code),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    if options.stack {
+        for value in i.stack.iter() {
+            eprintln!("{:?}", value);
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints the inferred stack effect of every function and the problems
+/// found by the checker
+fn check(path: &str) -> io::Result<()> {
+    let lexer = Lexer::new(read_source(path)?);
+    let tokens = lexer.lex();
+
+    let mut checker = Checker::new();
+    checker.check(&tokens);
+
+    for (name, effect) in checker.functions.iter() {
+        match effect {
+            Some(effect) => println!("fn {} {}", name, effect),
+            None => println!("fn {} ( ? )", name),
+        }
+    }
+
+    for diagnostic in checker.diagnostics.iter() {
+        eprintln!("Error: {}", diagnostic);
+    }
+
+    if !checker.diagnostics.is_empty() {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+/// Runs the tests in the files under `path`
+fn test(path: &str, options: &Options) -> io::Result<()> {
+    if !runner::run_tests(path, options.files, options.seed.unwrap_or(0))? {
+        std::process::exit(1);
+    }
 
     Ok(())
 }
+
+fn usage() -> ! {
+    eprintln!("Usage: jsl [run] [--stack] [--read-only|--sandbox] [--seed n] source.jsl [args...]");
+    eprintln!("       jsl check source.jsl");
+    eprintln!("       jsl test [--read-only|--sandbox] [--seed n] [dir]");
+    std::process::exit(2);
+}
+
+fn main() -> io::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let (command, rest) = match args.first().map(String::as_str) {
+        Some(command @ ("run" | "check" | "test")) => (command, &args[1..]),
+        _ => ("run", &args[..]),
+    };
+
+    // Flags a command doesn't use are rejected rather than ignored
+    let mut options = Options::default();
+    let mut rest = rest.iter().map(String::as_str);
+    let path = loop {
+        match rest.next() {
+            Some("--stack") if command == "run" => options.stack = true,
+            Some("--read-only") if command != "check" => options.files = FileAccess::Read,
+            Some("--sandbox") if command != "check" => options.files = FileAccess::None,
+            Some("--seed") if command != "check" => match rest.next().and_then(|n| n.parse().ok()) {
+                Some(seed) => options.seed = Some(seed),
+                None => usage(),
+            },
+            Some(flag) if flag.starts_with("--") => usage(),
+            path => break path,
+        }
+    };
+
+    match (command, path) {
+        ("run", Some(path)) => run(path, rest.map(String::from).collect(), &options),
+        ("check", Some(path)) => check(path),
+        ("test", path) => test(path.unwrap_or("."), &options),
+        _ => usage(),
+    }
+}
@@ -0,0 +1,61 @@
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+/// Output kept in memory, clones share the same bytes so one clone can be
+/// given to an interpreter and the other read after it ran
+#[derive(Clone, Default)]
+pub struct Buffer(Rc<RefCell<Vec<u8>>>);
+
+impl Buffer {
+    /// Everything written so far
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+}
+
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Output handed to a function as it is written, for hosts that show it
+/// their own way
+#[cfg_attr(not(test), allow(dead_code))]
+pub struct Callback<F: FnMut(&str)>(pub F);
+
+impl<F: FnMut(&str)> Write for Callback<F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (self.0)(&String::from_utf8_lossy(buf));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{interpreter::Interpreter, lexer::Lexer};
+
+    #[test]
+    fn callback_gets_every_chunk() {
+        let chunks = Rc::new(RefCell::new(Vec::<String>::new()));
+        let sink = chunks.clone();
+        let mut i = Interpreter::with_output(Box::new(Callback(move |s: &str| sink.borrow_mut().push(s.to_string()))));
+
+        let source = "1 put str a print \"b c\" put";
+        i.parse(Lexer::new(source.to_string()).lex()).unwrap();
+
+        assert!(chunks.borrow().len() > 1);
+        assert_eq!(chunks.borrow().concat(), "1\nab c\n");
+    }
+}
@@ -0,0 +1,103 @@
+use crate::{
+    interpreter::{FileAccess, Interpreter},
+    lexer::Lexer,
+    output::Buffer,
+    random::Rng,
+    read_source,
+    token::Token,
+};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// `.jsl` files under `path`, in order, or `path` itself when it is a file
+fn source_files(path: &Path) -> io::Result<Vec<PathBuf>> {
+    if path.is_file() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let mut entries: Vec<PathBuf> = fs::read_dir(path)?.map(|e| e.map(|e| e.path())).collect::<io::Result<_>>()?;
+    entries.sort();
+
+    let mut files = vec![];
+    for entry in entries {
+        if entry.is_dir() {
+            files.extend(source_files(&entry)?);
+        } else if entry.extension().is_some_and(|ext| ext == "jsl") {
+            files.push(entry);
+        }
+    }
+
+    Ok(files)
+}
+
+/// Runs the test `name` of a program in a fresh interpreter
+///
+/// The whole program runs first so the test sees its functions and lets,
+/// then the body of the test. Every test starts from the same `seed` so its
+/// random numbers are the same on every run. What it prints is only shown
+/// when it fails
+fn run_test(tokens: &[Token], name: &str, files: FileAccess, seed: u64) -> Result<(), String> {
+    let output = Buffer::default();
+    let mut i = Interpreter::with_output(Box::new(output.clone()));
+    i.input = Box::new(io::empty());
+    i.files = files;
+    i.rng = Rng::new(seed);
+    let mut result = i.parse(tokens.to_vec());
+
+    if result.is_ok() {
+        let test = i.tests.iter().find(|(n, _)| n == name).map(|(_, body)| body.clone());
+        result = match test {
+            Some(body) => i.run_closure(body),
+            None => return Err("test was not declared when the program ran".to_string()),
+        };
+    }
+
+    result.map_err(|e| {
+        let mut message = match i.error_location() {
+            Some(location) => format!("{}\n    at {}", e, location),
+            None => e.to_string(),
+        };
+
+        let output = output.contents();
+        if !output.is_empty() {
+            message.push_str("\n    output:");
+            for line in output.lines() {
+                message.push_str("\n    | ");
+                message.push_str(line);
+            }
+        }
+
+        message
+    })
+}
+
+/// Runs every test declared at the top level of the files under `path`,
+/// with `files` access, returns true when all of them pass
+pub fn run_tests(path: &str, files: FileAccess, seed: u64) -> io::Result<bool> {
+    let mut passed = 0;
+    let mut failed = 0;
+
+    for file in source_files(Path::new(path))? {
+        let tokens = Lexer::new(read_source(&file.to_string_lossy())?).lex();
+
+        for token in tokens.iter() {
+            if let Token::Test(name, line, _) = token {
+                match run_test(&tokens, name, files, seed) {
+                    Ok(()) => {
+                        println!("ok   {}:{} {}", file.display(), line, name);
+                        passed += 1;
+                    }
+                    Err(message) => {
+                        println!("FAIL {}:{} {}\n    {}", file.display(), line, name, message);
+                        failed += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    println!("{} passed, {} failed", passed, failed);
+
+    Ok(failed == 0)
+}
@@ -0,0 +1,48 @@
+/// Year, month and day of a number of days since 1970-01-01, in the
+/// proleptic Gregorian calendar
+fn civil(days: i64) -> (i64, u32, u32) {
+    // Eras of 400 years start on March 1st so the leap day is the last day
+    // of a year
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+/// Unix time in seconds as an ISO-8601 UTC date and time, like
+/// `2023-08-14T09:30:00Z`. Milliseconds are written when there are any.
+/// `None` when the time in milliseconds doesn't fit in an `i64`
+pub fn iso8601(seconds: f64) -> Option<String> {
+    let millis = (seconds * 1000.0).round();
+    // i64::MAX as f64 rounds up to 2^63, which is already out of range
+    if !(millis >= i64::MIN as f64 && millis < i64::MAX as f64) {
+        return None;
+    }
+    let millis = millis as i64;
+    let secs = millis.div_euclid(1000);
+    let (year, month, day) = civil(secs.div_euclid(86_400));
+    let time = secs.rem_euclid(86_400);
+
+    let mut text = format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    );
+    if millis.rem_euclid(1000) != 0 {
+        text.push_str(&format!(".{:03}", millis.rem_euclid(1000)));
+    }
+    text.push('Z');
+
+    Some(text)
+}
@@ -1,6 +1,8 @@
 use crate::types::*;
+use std::fmt;
+use std::rc::Rc;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
 pub enum Token {
     Number(f64),
     Add,
@@ -8,9 +10,62 @@ pub enum Token {
     Div,
     Mul,
     Mod,
+    Sqrt,
+    Pow,
+    Abs,
+    Floor,
+    Ceil,
+    Round,
+    Min,
+    Max,
+    Sin,
+    Cos,
+    Tan,
+    Log,
+    Exp,
+    Pi,
+    E,
+    Gcd,
+    Rand,
+    RandInt,
+    Shuffle,
+    Seed,
+    NowMs,
+    UnixTime,
+    Sleep,
+    IsoTime,
+    JsonParse,
+    JsonStringify,
     Swap,
     Rot,
+    Over,
+    Nip,
+    Tuck,
+    UnRot,
+    TwoDup,
+    TwoDrop,
+    TwoSwap,
+    Pick,
+    Roll,
+    Depth,
+    Clear,
     Put,
+    Print,
+    Format,
+    Printf,
+    ReadLine,
+    ReadNum,
+    ReadAll,
+    ReadFile,
+    WriteFile,
+    AppendFile,
+    ListDir,
+    Exists,
+    DeleteFile,
+    Args,
+    GetEnv,
+    SetEnv,
+    Exit,
     Eq,
     Noteq,
     Bigger,
@@ -22,16 +77,159 @@ pub enum Token {
     Drop,
     Str(String),
     Times,
-    Import(Vec<Token>),
+    Exec,
+    Len,
+    Nth,
+    Push,
+    Import(Rc<[Token]>),
     Let(String),
     Set(String),
     Mempop,
     Memusage,
+    Gc,
+    Heapusage,
     Function(Function),
-    Object(Vec<(String, Token)>),
+    Object(Vec<(String, Rc<[Token]>)>),
     Get(String),
+    SetProp(String),
+    Has(String),
+    Delete(String),
+    Keys,
+    MapNew,
+    MapSet,
+    MapGet,
+    MapHas,
+    MapDel,
+    MapKeys,
+    MapValues,
+    MapLen,
     Call(String),
+    Throw,
+    /// Body and handler of `try { } catch { }`
+    Try(Rc<[Token]>, Rc<[Token]>),
+    Assert,
+    AssertEq,
+    AssertStack,
+    /// Name, source line and body of `test name { }`
+    Test(String, usize, Rc<[Token]>),
     Ident(String),
-    Array(Vec<Token>),
-    Scope(Vec<Token>),
+    Array(Rc<[Token]>),
+    Scope(Rc<[Token]>),
+}
+
+/// Shows the token the way it is written in source
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Number(n) => write!(f, "{}", n),
+            Self::Add => write!(f, "add"),
+            Self::Minus => write!(f, "minus"),
+            Self::Div => write!(f, "div"),
+            Self::Mul => write!(f, "mul"),
+            Self::Mod => write!(f, "mod"),
+            Self::Sqrt => write!(f, "sqrt"),
+            Self::Pow => write!(f, "pow"),
+            Self::Abs => write!(f, "abs"),
+            Self::Floor => write!(f, "floor"),
+            Self::Ceil => write!(f, "ceil"),
+            Self::Round => write!(f, "round"),
+            Self::Min => write!(f, "min"),
+            Self::Max => write!(f, "max"),
+            Self::Sin => write!(f, "sin"),
+            Self::Cos => write!(f, "cos"),
+            Self::Tan => write!(f, "tan"),
+            Self::Log => write!(f, "log"),
+            Self::Exp => write!(f, "exp"),
+            Self::Pi => write!(f, "pi"),
+            Self::E => write!(f, "e"),
+            Self::Gcd => write!(f, "gcd"),
+            Self::Rand => write!(f, "rand"),
+            Self::RandInt => write!(f, "randint"),
+            Self::Shuffle => write!(f, "shuffle"),
+            Self::Seed => write!(f, "seed"),
+            Self::NowMs => write!(f, "now_ms"),
+            Self::UnixTime => write!(f, "unixtime"),
+            Self::Sleep => write!(f, "sleep"),
+            Self::IsoTime => write!(f, "isotime"),
+            Self::JsonParse => write!(f, "json_parse"),
+            Self::JsonStringify => write!(f, "json_stringify"),
+            Self::Swap => write!(f, "swap"),
+            Self::Rot => write!(f, "rot"),
+            Self::Over => write!(f, "over"),
+            Self::Nip => write!(f, "nip"),
+            Self::Tuck => write!(f, "tuck"),
+            Self::UnRot => write!(f, "-rot"),
+            Self::TwoDup => write!(f, "2dup"),
+            Self::TwoDrop => write!(f, "2drop"),
+            Self::TwoSwap => write!(f, "2swap"),
+            Self::Pick => write!(f, "pick"),
+            Self::Roll => write!(f, "roll"),
+            Self::Depth => write!(f, "depth"),
+            Self::Clear => write!(f, "clear"),
+            Self::Put => write!(f, "put"),
+            Self::Print => write!(f, "print"),
+            Self::Format => write!(f, "format"),
+            Self::Printf => write!(f, "printf"),
+            Self::ReadLine => write!(f, "readline"),
+            Self::ReadNum => write!(f, "readnum"),
+            Self::ReadAll => write!(f, "readall"),
+            Self::ReadFile => write!(f, "readfile"),
+            Self::WriteFile => write!(f, "writefile"),
+            Self::AppendFile => write!(f, "appendfile"),
+            Self::ListDir => write!(f, "listdir"),
+            Self::Exists => write!(f, "exists"),
+            Self::DeleteFile => write!(f, "deletefile"),
+            Self::Args => write!(f, "args"),
+            Self::GetEnv => write!(f, "getenv"),
+            Self::SetEnv => write!(f, "setenv"),
+            Self::Exit => write!(f, "exit"),
+            Self::Eq => write!(f, "eq"),
+            Self::Noteq => write!(f, "noteq"),
+            Self::Bigger => write!(f, "bigger"),
+            Self::Smaller => write!(f, "smaller"),
+            Self::Then => write!(f, "then"),
+            Self::True => write!(f, "true"),
+            Self::False => write!(f, "false"),
+            Self::Dup => write!(f, "dup"),
+            Self::Drop => write!(f, "drop"),
+            Self::Str(content) => write!(f, "str {}", content),
+            Self::Times => write!(f, "times"),
+            Self::Exec => write!(f, "exec"),
+            Self::Len => write!(f, "len"),
+            Self::Nth => write!(f, "nth"),
+            Self::Push => write!(f, "push"),
+            Self::Import(_) => write!(f, "import"),
+            Self::Let(name) => write!(f, "let {}", name),
+            Self::Set(name) => write!(f, "set {}", name),
+            Self::Mempop => write!(f, "mempop"),
+            Self::Memusage => write!(f, "memusage"),
+            Self::Gc => write!(f, "gc"),
+            Self::Heapusage => write!(f, "heapusage"),
+            Self::Function(func) => write!(f, "fn {}", func.name),
+            Self::Object(_) => write!(f, "object {{ ... }}"),
+            Self::Get(name) => write!(f, "get {}", name),
+            Self::SetProp(name) => write!(f, "setp {}", name),
+            Self::Has(name) => write!(f, "has {}", name),
+            Self::Delete(name) => write!(f, "delete {}", name),
+            Self::Keys => write!(f, "keys"),
+            Self::MapNew => write!(f, "mapnew"),
+            Self::MapSet => write!(f, "mapset"),
+            Self::MapGet => write!(f, "mapget"),
+            Self::MapHas => write!(f, "maphas"),
+            Self::MapDel => write!(f, "mapdel"),
+            Self::MapKeys => write!(f, "mapkeys"),
+            Self::MapValues => write!(f, "mapvalues"),
+            Self::MapLen => write!(f, "maplen"),
+            Self::Call(name) => write!(f, "call {}", name),
+            Self::Throw => write!(f, "throw"),
+            Self::Try(..) => write!(f, "try {{ ... }} catch {{ ... }}"),
+            Self::Assert => write!(f, "assert"),
+            Self::AssertEq => write!(f, "assert_eq"),
+            Self::AssertStack => write!(f, "assert_stack"),
+            Self::Test(name, ..) => write!(f, "test {}", name),
+            Self::Ident(name) => write!(f, "{}", name),
+            Self::Array(_) => write!(f, "[ ... ]"),
+            Self::Scope(_) => write!(f, "{{ ... }}"),
+        }
+    }
 }
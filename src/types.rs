@@ -1,30 +1,100 @@
 use crate::{interpreter::StackType, token::Token};
+use std::fmt;
+use std::rc::Rc;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
 pub struct Let {
     pub name: String,
     pub value: StackType,
 }
 
-#[derive(Debug, Clone)]
+/// Declared stack effect of a function, `( a b -- c )`
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct StackEffect {
+    pub inputs: Vec<String>,
+    pub outputs: Vec<String>,
+}
+
+/// Type annotation of a function parameter or result, `w:num`
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Type {
+    Num,
+    Str,
+    Arr,
+    Ptr,
+    Quote,
+    Any,
+}
+
+impl Type {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "num" => Some(Self::Num),
+            "str" => Some(Self::Str),
+            "arr" => Some(Self::Arr),
+            "ptr" => Some(Self::Ptr),
+            "quote" => Some(Self::Quote),
+            "any" => Some(Self::Any),
+            _ => None,
+        }
+    }
+
+    /// Type of a value
+    pub fn of(value: &StackType) -> Self {
+        match value {
+            StackType::Float(_) => Self::Num,
+            StackType::String(_) => Self::Str,
+            StackType::Array(_) => Self::Arr,
+            StackType::Pointer(_) => Self::Ptr,
+            StackType::Quote(_) => Self::Quote,
+        }
+    }
+
+    /// True when a value of type `other` can be used where this type is expected
+    pub fn accepts(&self, other: Self) -> bool {
+        *self == Self::Any || *self == other
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Num => "num",
+            Self::Str => "str",
+            Self::Arr => "arr",
+            Self::Ptr => "ptr",
+            Self::Quote => "quote",
+            Self::Any => "any",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
 pub struct Function {
     pub name: String,
     pub args: Vec<Let>,
-    pub memory: Vec<Let>,
-    pub scope: Vec<Token>,
+    /// Annotated type of every arg, `Type::Any` when it has none
+    pub arg_types: Vec<Type>,
+    /// Annotated result types, the last one is on top of stack
+    pub results: Option<Vec<Type>>,
+    pub scope: Rc<[Token]>,
+    pub effect: Option<StackEffect>,
 }
 
 impl Function {
     pub fn new(name: String, args: Vec<Let>) -> Self {
         Self {
             name,
+            arg_types: vec![Type::Any; args.len()],
             args,
-            memory: vec![],
-            scope: vec![]
+            results: None,
+            scope: Rc::from([]),
+            effect: None,
         }
     }
 
-    pub fn set_scope(&mut self, tokens: Vec<Token>) {
+    pub fn set_scope(&mut self, tokens: Rc<[Token]>) {
         self.scope = tokens;
     }
 }
@@ -0,0 +1,193 @@
+//! Golden tests: runs programs with the `jsl` binary and compares what they
+//! print with files written next to them
+//!
+//! For every `tests/golden/name.jsl`
+//! - `name.in` is given to the program as stdin, if it exists
+//! - `name.out` is the expected stdout, empty when missing
+//! - `name.stack` is the expected stack left at the end, one value per line
+//! - `name.err` is the expected error, the program must fail with it
+//!
+//! Programs get a scratch directory in `JSL_TMPDIR` for the files they write.
+//! `JSL_BLESS=1 cargo test` rewrites the expected files from the actual output.
+//! The examples of `README.md` written in ```` ```jsl ```` blocks are run too,
+//! and compared with the ```` ```output ```` block that follows them if any,
+//! they run in a scratch directory
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// What a run of the binary printed
+struct Run {
+    stdout: String,
+    stderr: String,
+    success: bool,
+}
+
+/// Runs the binary in `dir`
+fn jsl(args: &[&str], input: &str, dir: &Path) -> Run {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_jsl"))
+        .args(args)
+        .current_dir(dir)
+        .env("JSL_TMPDIR", env!("CARGO_TARGET_TMPDIR"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to run jsl");
+
+    // Programs that don't read their input may exit before it is written
+    let _ = child.stdin.take().unwrap().write_all(input.as_bytes());
+    let output = child.wait_with_output().unwrap();
+
+    Run {
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        success: output.status.success(),
+    }
+}
+
+fn bless() -> bool {
+    std::env::var_os("JSL_BLESS").is_some()
+}
+
+/// Compares `actual` with the expected file, or writes it when blessing.
+/// A missing file is expected to be empty unless `required`
+fn compare(path: &Path, actual: &str, required: bool, failures: &mut Vec<String>) {
+    if bless() {
+        if actual.is_empty() && !required {
+            let _ = fs::remove_file(path);
+        } else {
+            fs::write(path, actual).unwrap();
+        }
+        return;
+    }
+
+    let expected = fs::read_to_string(path).unwrap_or_default();
+    if expected != actual {
+        failures.push(format!(
+            "{}\n--- expected\n{}--- actual\n{}",
+            path.display(),
+            expected,
+            actual
+        ));
+    }
+}
+
+fn golden_files() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == "jsl"))
+        .collect();
+    files.sort();
+
+    files
+}
+
+#[test]
+fn golden() {
+    let mut failures = vec![];
+
+    for file in golden_files() {
+        let input = fs::read_to_string(file.with_extension("in")).unwrap_or_default();
+        let run = jsl(
+            &["run", "--stack", file.to_str().unwrap()],
+            &input,
+            Path::new(env!("CARGO_MANIFEST_DIR")),
+        );
+        let err = file.with_extension("err");
+
+        compare(&file.with_extension("out"), &run.stdout, false, &mut failures);
+
+        if run.success {
+            if err.exists() && !bless() {
+                failures.push(format!("{} should fail with\n{}", file.display(), fs::read_to_string(&err).unwrap()));
+            }
+            compare(&file.with_extension("stack"), &run.stderr, false, &mut failures);
+        } else {
+            compare(&err, &run.stderr, true, &mut failures);
+        }
+    }
+
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+/// A ```` ```jsl ```` block of the readme, with the ```` ```output ```` block
+/// after it
+struct Example {
+    line: usize,
+    args: Vec<String>,
+    source: String,
+    output: Option<String>,
+}
+
+/// Reads the fenced blocks of the readme, ```` ```jsl check ```` blocks run
+/// with `jsl check`
+fn readme_examples() -> Vec<Example> {
+    let readme = fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join("README.md")).unwrap();
+    let mut examples: Vec<Example> = vec![];
+    let mut lines = readme.lines().enumerate();
+
+    while let Some((n, line)) = lines.next() {
+        // Skip code written on one line like ```cargo build```
+        let Some(info) = line.strip_prefix("```").filter(|i| !i.contains("```")) else {
+            continue;
+        };
+
+        let body: String = lines
+            .by_ref()
+            .take_while(|(_, l)| !l.starts_with("```"))
+            .map(|(_, l)| format!("{}\n", l))
+            .collect();
+
+        let mut words = info.split_whitespace();
+        match words.next() {
+            Some("jsl") => examples.push(Example {
+                line: n + 1,
+                args: words.map(String::from).collect(),
+                source: body,
+                output: None,
+            }),
+            Some("output") => {
+                if let Some(example) = examples.last_mut().filter(|e| e.output.is_none()) {
+                    example.output = Some(body);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    examples
+}
+
+#[test]
+fn readme() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("readme-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let mut failures = vec![];
+
+    for example in readme_examples() {
+        let path = dir.join(format!("line{}.jsl", example.line));
+        fs::write(&path, &example.source).unwrap();
+
+        let mut args: Vec<&str> = example.args.iter().map(String::as_str).collect();
+        args.push(path.to_str().unwrap());
+        let run = jsl(&args, "", &dir);
+        let actual = run.stdout + &run.stderr;
+
+        match &example.output {
+            Some(expected) if *expected != actual => failures.push(format!(
+                "README.md:{}\n--- expected\n{}--- actual\n{}",
+                example.line, expected, actual
+            )),
+            None if !run.success => failures.push(format!("README.md:{} failed\n{}", example.line, actual)),
+            _ => {}
+        }
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}